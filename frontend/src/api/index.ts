@@ -22,11 +22,112 @@ export interface Video {
   author_id: string;
   author: User;
   published_at: string;
+  status: "uploaded" | "processing" | "ready" | "failed";
+  visibility: "public" | "unlisted" | "private";
+  updated_at: string;
+  deleted_at: string | null;
+  comments_enabled: boolean;
+  pinned_comment_id: number | null;
+  thumbnails: Thumbnail[];
+  metadata?: MediaMetadata | null;
+  hls_url?: string | null;
+  like_count: number;
+  dislike_count: number;
+  view_count: number;
+  is_liking: boolean | null;
+  highlight?: SearchHighlight;
+}
+
+/** Only in search results, matches are between the requested markers */
+export interface SearchHighlight {
+  title: string;
+  description: string;
+}
+
+export interface MediaMetadata {
+  container: string;
+  width: number;
+  height: number;
+  frame_rate: number | null;
+  video_codec: string;
+  audio_codec: string | null;
+  audio_channel_layout: string | null;
+  bit_rate: number | null;
+  rotation: number;
+  has_captions: boolean;
+}
+
+export interface Thumbnail {
+  size: "small" | "medium" | "large";
+  width: number;
+  height: number;
+  url: string;
+}
+
+export interface Comment {
+  id: number;
+  video_id: number;
+  author_id: number;
+  parent_id: number | null;
+  content: string;
+  created_at: string;
+  updated_at: string;
+  author: User;
+  likes: number;
+  dislikes: number;
+  reply_count: number;
 }
 
 export interface User {
   id: number;
   username: string;
+  subscriber_count: number;
+  display_name: string | null;
+  bio: string;
+  links: string[];
+  avatars: Thumbnail[];
+  banners: Thumbnail[];
+}
+
+export interface Page<T> {
+  items: T[];
+  next_cursor: string | null;
+}
+
+export interface Playlist {
+  id: number;
+  owner_id: number;
+  kind: "custom" | "watch_later";
+  title: string;
+  description: string;
+  visibility: "public" | "unlisted" | "private";
+  created_at: string;
+  updated_at: string;
+}
+
+export interface PlaylistItem extends Video {
+  position: number;
+}
+
+export interface PlaylistWithItems extends Playlist {
+  owner: User;
+  items: Page<PlaylistItem>;
+}
+
+export interface HistoryEntry extends Video {
+  position_seconds: number;
+  watched_fraction: number;
+  watched_at: string;
+}
+
+export interface History extends Page<HistoryEntry> {
+  paused: boolean;
+}
+
+export interface Channel extends User {
+  is_subscribed: boolean | null;
+  total_views: number;
+  videos: Page<Video>;
 }
 
 export async function fetcher<T>(url: string): Promise<T> {
@@ -34,8 +135,33 @@ export async function fetcher<T>(url: string): Promise<T> {
   return response.data;
 }
 
-export function resolveVideo(bucket: string): string {
-  return `http://localhost:9000/videos/${bucket}`;
+// Tell the server that a video is being played, so that it counts a view
+export async function recordView(id: number): Promise<void> {
+  await api.post(`/videos/${id}/views`);
+}
+
+export function resolveVideo(id: number): string {
+  return `${api.defaults.baseURL}/videos/${id}/stream`;
+}
+
+// The HLS renditions when they exist and the browser plays HLS natively,
+// otherwise the original upload
+export function resolvePlayback(video: Video): { src: string; type?: string } {
+  const hlsType = "application/vnd.apple.mpegurl";
+
+  if (video.hls_url && document.createElement("video").canPlayType(hlsType)) {
+    return { src: `${api.defaults.baseURL}${video.hls_url}`, type: hlsType };
+  }
+
+  return { src: resolveVideo(video.id) };
+}
+
+export function resolveThumbnail(
+  thumbnails: Thumbnail[],
+  size: Thumbnail["size"]
+): string | undefined {
+  const thumbnail = thumbnails.find((t) => t.size === size);
+  return thumbnail && api.defaults.baseURL + thumbnail.url;
 }
 
 export default api;
@@ -0,0 +1 @@
+alter table videos drop column master_playlist;
@@ -0,0 +1 @@
+alter table videos add column master_playlist varchar;
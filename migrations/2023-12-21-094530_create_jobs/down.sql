@@ -0,0 +1,5 @@
+drop table jobs;
+drop type job_state;
+
+alter table videos drop column status;
+drop type video_status;
@@ -0,0 +1,24 @@
+create type video_status as enum ('uploaded', 'processing', 'ready', 'failed');
+
+-- Videos uploaded before the job queue existed were processed inline
+alter table videos add column status video_status not null default 'ready';
+alter table videos alter column status set default 'uploaded';
+
+create type job_state as enum ('pending', 'running', 'done', 'failed');
+
+create table jobs (
+  id serial primary key,
+  video_id int not null references videos(id),
+  state job_state not null default 'pending',
+  attempts int not null default 0,
+  max_attempts int not null default 5,
+  last_error text,
+  run_at timestamptz not null default now(),
+  locked_at timestamptz,
+  created_at timestamptz not null default now(),
+  updated_at timestamptz not null default now()
+);
+
+create index jobs_runnable_idx on jobs (run_at) where state in ('pending', 'running');
+
+select diesel_manage_updated_at('jobs');
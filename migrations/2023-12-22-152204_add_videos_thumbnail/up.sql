@@ -0,0 +1 @@
+alter table videos add column thumbnail uuid;
@@ -0,0 +1,14 @@
+create table video_metadata (
+  video_id int primary key references videos(id) on delete cascade,
+  container varchar not null,
+  width int not null,
+  height int not null,
+  frame_rate double precision,
+  video_codec varchar not null,
+  audio_codec varchar,
+  audio_channel_layout varchar,
+  bit_rate bigint,
+  rotation int not null default 0
+);
+
+create index video_metadata_height_idx on video_metadata (height);
@@ -0,0 +1,13 @@
+create table uploads (
+  id uuid primary key,
+  user_id int not null references users(id),
+  title varchar not null,
+  description text not null,
+  upload_length bigint not null,
+  upload_offset bigint not null default 0,
+  video_id int references videos(id),
+  created_at timestamptz not null default now(),
+  updated_at timestamptz not null default now()
+);
+
+select diesel_manage_updated_at('uploads');
@@ -0,0 +1 @@
+drop table multipart_uploads;
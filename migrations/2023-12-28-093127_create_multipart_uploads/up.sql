@@ -0,0 +1,10 @@
+create table multipart_uploads (
+  id uuid primary key,
+  user_id int not null references users(id),
+  s3_upload_id varchar not null,
+  title varchar not null,
+  description text not null,
+  size bigint not null,
+  part_size bigint not null,
+  created_at timestamptz not null default now()
+);
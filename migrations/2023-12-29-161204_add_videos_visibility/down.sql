@@ -0,0 +1,5 @@
+alter table multipart_uploads drop column visibility;
+alter table uploads drop column visibility;
+alter table videos drop column visibility;
+
+drop type visibility;
@@ -0,0 +1,6 @@
+create type visibility as enum ('public', 'unlisted', 'private');
+
+alter table videos add column visibility visibility not null default 'public';
+
+alter table uploads add column visibility visibility not null default 'public';
+alter table multipart_uploads add column visibility visibility not null default 'public';
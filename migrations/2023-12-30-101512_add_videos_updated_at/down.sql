@@ -0,0 +1,3 @@
+drop trigger if exists set_updated_at on videos;
+
+alter table videos drop column updated_at;
@@ -0,0 +1,3 @@
+alter table videos add column updated_at timestamptz not null default now();
+
+select diesel_manage_updated_at('videos');
@@ -0,0 +1,15 @@
+alter table uploads drop constraint uploads_video_id_fkey;
+alter table uploads add constraint uploads_video_id_fkey
+  foreign key (video_id) references videos(id);
+
+alter table jobs drop constraint jobs_video_id_fkey;
+alter table jobs add constraint jobs_video_id_fkey
+  foreign key (video_id) references videos(id);
+
+alter table likes drop constraint likes_video_id_fkey;
+alter table likes add constraint likes_video_id_fkey
+  foreign key (video_id) references videos(id);
+
+drop index videos_deleted_at_idx;
+
+alter table videos drop column deleted_at;
@@ -0,0 +1,16 @@
+alter table videos add column deleted_at timestamptz;
+
+create index videos_deleted_at_idx on videos (deleted_at) where deleted_at is not null;
+
+-- Purging a video from the trash takes everything that refers to it along
+alter table likes drop constraint likes_video_id_fkey;
+alter table likes add constraint likes_video_id_fkey
+  foreign key (video_id) references videos(id) on delete cascade;
+
+alter table jobs drop constraint jobs_video_id_fkey;
+alter table jobs add constraint jobs_video_id_fkey
+  foreign key (video_id) references videos(id) on delete cascade;
+
+alter table uploads drop constraint uploads_video_id_fkey;
+alter table uploads add constraint uploads_video_id_fkey
+  foreign key (video_id) references videos(id) on delete cascade;
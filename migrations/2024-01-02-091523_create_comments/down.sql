@@ -0,0 +1,5 @@
+alter table videos drop column pinned_comment_id;
+alter table videos drop column comments_enabled;
+
+drop table comment_likes;
+drop table comments;
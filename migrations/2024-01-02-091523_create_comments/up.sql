@@ -0,0 +1,25 @@
+create table comments (
+  id serial primary key,
+  video_id int not null references videos(id) on delete cascade,
+  author_id int not null references users(id),
+  -- Replies only go one level deep
+  parent_id int references comments(id) on delete cascade,
+  content text not null,
+  created_at timestamptz not null default now(),
+  updated_at timestamptz not null default now()
+);
+
+create index comments_video_id_idx on comments (video_id, created_at) where parent_id is null;
+create index comments_parent_id_idx on comments (parent_id, created_at);
+
+select diesel_manage_updated_at('comments');
+
+create table comment_likes (
+  user_id int references users(id),
+  comment_id int references comments(id) on delete cascade,
+  is_liking boolean not null,
+  primary key(user_id, comment_id)
+);
+
+alter table videos add column comments_enabled boolean not null default true;
+alter table videos add column pinned_comment_id int references comments(id) on delete set null;
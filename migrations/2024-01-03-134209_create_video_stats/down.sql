@@ -0,0 +1,4 @@
+drop trigger update_video_stats on likes;
+drop function update_video_stats_likes();
+
+drop table video_stats;
@@ -0,0 +1,35 @@
+-- Kept apart from videos so that likes don't touch videos.updated_at
+create table video_stats (
+  video_id int primary key references videos(id) on delete cascade,
+  like_count bigint not null default 0,
+  dislike_count bigint not null default 0
+);
+
+insert into video_stats (video_id, like_count, dislike_count)
+select video_id, count(*) filter (where is_liking), count(*) filter (where not is_liking)
+from likes
+group by video_id;
+
+create function update_video_stats_likes() returns trigger as $$
+begin
+  if tg_op in ('UPDATE', 'DELETE') then
+    update video_stats set
+      like_count = like_count - old.is_liking::int,
+      dislike_count = dislike_count - (not old.is_liking)::int
+    where video_id = old.video_id;
+  end if;
+
+  if tg_op in ('INSERT', 'UPDATE') then
+    insert into video_stats (video_id, like_count, dislike_count)
+    values (new.video_id, new.is_liking::int, (not new.is_liking)::int)
+    on conflict (video_id) do update set
+      like_count = video_stats.like_count + excluded.like_count,
+      dislike_count = video_stats.dislike_count + excluded.dislike_count;
+  end if;
+
+  return null;
+end;
+$$ language plpgsql;
+
+create trigger update_video_stats after insert or update or delete on likes
+  for each row execute procedure update_video_stats_likes();
@@ -0,0 +1,8 @@
+drop index videos_author_id_published_at_idx;
+
+drop trigger update_subscriber_count on subscriptions;
+drop function update_subscriber_count();
+
+alter table users drop column subscriber_count;
+
+drop table subscriptions;
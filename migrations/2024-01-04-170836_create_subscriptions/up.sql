@@ -0,0 +1,29 @@
+create table subscriptions (
+  subscriber_id int references users(id) on delete cascade,
+  channel_id int references users(id) on delete cascade,
+  created_at timestamptz not null default now(),
+  primary key(subscriber_id, channel_id),
+  check (subscriber_id <> channel_id)
+);
+
+create index subscriptions_channel_id_idx on subscriptions (channel_id);
+
+alter table users add column subscriber_count bigint not null default 0;
+
+create function update_subscriber_count() returns trigger as $$
+begin
+  if tg_op = 'INSERT' then
+    update users set subscriber_count = subscriber_count + 1 where id = new.channel_id;
+  else
+    update users set subscriber_count = subscriber_count - 1 where id = old.channel_id;
+  end if;
+
+  return null;
+end;
+$$ language plpgsql;
+
+create trigger update_subscriber_count after insert or delete on subscriptions
+  for each row execute procedure update_subscriber_count();
+
+-- The feed lists the latest videos of a few authors
+create index videos_author_id_published_at_idx on videos (author_id, published_at desc, id desc);
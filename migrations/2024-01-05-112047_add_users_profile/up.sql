@@ -0,0 +1,6 @@
+alter table users
+  add column display_name varchar,
+  add column bio text not null default '',
+  add column links text[] not null default '{}' check (array_position(links, null) is null),
+  add column avatar uuid,
+  add column banner uuid;
@@ -0,0 +1,6 @@
+alter table likes drop column created_at;
+
+drop table playlist_items;
+drop table playlists;
+
+drop type playlist_kind;
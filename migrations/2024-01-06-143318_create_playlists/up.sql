@@ -0,0 +1,33 @@
+create type playlist_kind as enum ('custom', 'watch_later');
+
+create table playlists (
+  id serial primary key,
+  owner_id int not null references users(id) on delete cascade,
+  kind playlist_kind not null default 'custom',
+  title varchar not null,
+  description text not null default '',
+  visibility visibility not null default 'public',
+  created_at timestamptz not null default now(),
+  updated_at timestamptz not null default now()
+);
+
+create index playlists_owner_id_idx on playlists (owner_id);
+
+-- Built-in playlists are created on first use, once per user
+create unique index playlists_watch_later_idx on playlists (owner_id) where kind = 'watch_later';
+
+select diesel_manage_updated_at('playlists');
+
+create table playlist_items (
+  playlist_id int not null references playlists(id) on delete cascade,
+  video_id int not null references videos(id) on delete cascade,
+  -- Only orders the items, removals leave gaps
+  position int not null,
+  added_at timestamptz not null default now(),
+  primary key (playlist_id, video_id),
+  -- Deferred so that reordering can swap positions
+  unique (playlist_id, position) deferrable initially deferred
+);
+
+-- Orders the liked videos playlist, earlier likes all get the migration time
+alter table likes add column created_at timestamptz not null default now();
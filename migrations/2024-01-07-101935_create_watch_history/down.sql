@@ -0,0 +1,3 @@
+alter table users drop column history_paused;
+
+drop table watch_history;
@@ -0,0 +1,11 @@
+create table watch_history (
+  user_id int not null references users(id) on delete cascade,
+  video_id int not null references videos(id) on delete cascade,
+  position_seconds bigint not null,
+  watched_at timestamptz not null default now(),
+  primary key (user_id, video_id)
+);
+
+create index watch_history_user_id_watched_at_idx on watch_history (user_id, watched_at);
+
+alter table users add column history_paused boolean not null default false;
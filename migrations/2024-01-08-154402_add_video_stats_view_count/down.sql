@@ -0,0 +1 @@
+alter table video_stats drop column view_count;
@@ -0,0 +1 @@
+alter table video_stats add column view_count bigint not null default 0;
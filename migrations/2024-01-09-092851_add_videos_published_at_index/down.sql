@@ -0,0 +1 @@
+drop index videos_published_at_id_idx;
@@ -0,0 +1,2 @@
+-- Keyset pagination of listings sorted by date
+create index videos_published_at_id_idx on videos (published_at, id) where deleted_at is null;
@@ -0,0 +1 @@
+alter table video_metadata drop column has_captions;
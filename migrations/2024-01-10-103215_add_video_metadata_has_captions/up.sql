@@ -0,0 +1,2 @@
+-- Videos probed before this are assumed to have none
+alter table video_metadata add column has_captions boolean not null default false;
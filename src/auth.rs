@@ -1,7 +1,8 @@
 use crate::{config, errors, models, schema, AppState};
 use axum::{
-    extract::{Request, State},
-    http::{header, StatusCode},
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
     middleware::Next,
     response::Response,
 };
@@ -21,17 +22,48 @@ pub async fn middleware(
     mut req: Request,
     next: Next,
 ) -> Result<Response, (StatusCode, String)> {
-    let token = req
-        .headers()
-        .get(header::AUTHORIZATION)
-        .and_then(|auth_header| auth_header.to_str().ok())
-        .and_then(|auth_value| auth_value.strip_prefix("Bearer "));
-
-    let token = token.ok_or((
+    let token = bearer_token(req.headers()).ok_or((
         StatusCode::UNAUTHORIZED,
         "You are not logged in".to_string(),
     ))?;
 
+    let logged_user = authenticate(&state, token).await?;
+
+    req.extensions_mut().insert(logged_user);
+
+    Ok(next.run(req).await)
+}
+
+/// Extractor for routes open to everyone that behave differently for logged
+/// in users. A request without a token is anonymous, one with an invalid
+/// token is still rejected.
+#[derive(Debug)]
+pub struct OptionalUser(pub Option<models::User>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for OptionalUser {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(token) = bearer_token(&parts.headers) else {
+            return Ok(OptionalUser(None));
+        };
+
+        Ok(OptionalUser(Some(authenticate(state, token).await?)))
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|auth_header| auth_header.to_str().ok())
+        .and_then(|auth_value| auth_value.strip_prefix("Bearer "))
+}
+
+async fn authenticate(state: &AppState, token: &str) -> Result<models::User, (StatusCode, String)> {
     let jwt_secret = config::config().await.jwt_secret();
 
     let claims = jsonwebtoken::decode::<JwtClaims>(
@@ -46,16 +78,12 @@ pub async fn middleware(
 
     let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
 
-    let logged_user = users
+    users
         .select(models::User::as_select())
         .find(claims.user_id)
         .first(&mut conn)
         .await
         .optional()
         .map_err(errors::internal_error)?
-        .ok_or((StatusCode::UNAUTHORIZED, "Invalid token".to_string()))?;
-
-    req.extensions_mut().insert(logged_user);
-
-    Ok(next.run(req).await)
+        .ok_or((StatusCode::UNAUTHORIZED, "Invalid token".to_string()))
 }
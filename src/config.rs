@@ -1,5 +1,8 @@
 use dotenvy::dotenv;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 use std::env;
+use std::path::{Path, PathBuf};
 use tokio::sync::OnceCell;
 
 #[derive(Debug)]
@@ -14,19 +17,84 @@ struct DatabaseConfig {
 }
 
 #[derive(Debug)]
-struct S3Config {
+pub struct S3Config {
     base_url: String,
     access_key: String,
     secret_key: String,
     bucket: String,
 }
 
+impl S3Config {
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn access_key(&self) -> &str {
+        &self.access_key
+    }
+
+    pub fn secret_key(&self) -> &str {
+        &self.secret_key
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+}
+
+/// Where videos and thumbnails are kept, see `storage`
+#[derive(Debug)]
+pub enum StorageConfig {
+    S3(S3Config),
+    /// A directory on this machine, for development
+    Local {
+        dir: PathBuf,
+    },
+}
+
+#[derive(Debug)]
+struct WorkerConfig {
+    enabled: bool,
+}
+
+#[derive(Debug)]
+struct UploadConfig {
+    dir: PathBuf,
+    max_size: u64,
+    expiry_hours: i64,
+}
+
+#[derive(Debug)]
+struct TrashConfig {
+    retention_days: i64,
+}
+
+#[derive(Debug)]
+struct GcConfig {
+    grace_hours: i64,
+    dry_run: bool,
+}
+
+#[derive(Debug)]
+struct ViewsConfig {
+    min_watch_seconds: u64,
+    dedup_hours: u64,
+    flush_seconds: u64,
+    trust_forwarded_for: bool,
+}
+
 #[derive(Debug)]
 pub struct Config {
     server: ServerConfig,
     db: DatabaseConfig,
-    s3: S3Config,
+    storage: StorageConfig,
+    worker: WorkerConfig,
+    upload: UploadConfig,
+    trash: TrashConfig,
+    gc: GcConfig,
+    views: ViewsConfig,
     jwt_secret: String,
+    playback_secret: String,
 }
 
 impl Config {
@@ -46,20 +114,72 @@ impl Config {
         &self.jwt_secret
     }
 
-    pub fn s3_base_url(&self) -> &str {
-        &self.s3.base_url
+    /// Key signing the playback URLs served by the API, derived from the JWT
+    /// secret when `PLAYBACK_SECRET` isn't set
+    pub fn playback_secret(&self) -> &str {
+        &self.playback_secret
+    }
+
+    pub fn storage(&self) -> &StorageConfig {
+        &self.storage
     }
 
-    pub fn s3_access_key(&self) -> &str {
-        &self.s3.access_key
+    /// Whether the API server also runs the video processing worker
+    pub fn worker_enabled(&self) -> bool {
+        self.worker.enabled
     }
 
-    pub fn s3_secret_key(&self) -> &str {
-        &self.s3.secret_key
+    /// Where partially received resumable uploads are kept
+    pub fn upload_dir(&self) -> &Path {
+        &self.upload.dir
     }
 
-    pub fn s3_bucket(&self) -> &str {
-        &self.s3.bucket
+    /// Largest upload accepted, in bytes
+    pub fn upload_max_size(&self) -> u64 {
+        self.upload.max_size
+    }
+
+    /// How long an unfinished upload is kept after its last activity
+    pub fn upload_expiry(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.upload.expiry_hours)
+    }
+
+    /// How long deleted videos can be restored before being purged
+    pub fn trash_retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.trash.retention_days)
+    }
+
+    /// Age under which an object without owner may still be in the middle of
+    /// being stored, and is left alone
+    pub fn gc_grace_period(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.gc.grace_hours)
+    }
+
+    /// Whether the garbage collector only reports orphaned objects
+    pub fn gc_dry_run(&self) -> bool {
+        self.gc.dry_run
+    }
+
+    /// How long a viewer watches a video before it counts as a view, or the
+    /// whole video when shorter
+    pub fn view_min_watch(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.views.min_watch_seconds)
+    }
+
+    /// How long after a counted view the same viewer counts again
+    pub fn view_dedup_window(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.views.dedup_hours * 60 * 60)
+    }
+
+    /// How often buffered views are written to the database
+    pub fn view_flush_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.views.flush_seconds)
+    }
+
+    /// Whether anonymous viewers are told apart by the `X-Forwarded-For`
+    /// header rather than the peer address, when behind a proxy
+    pub fn view_trust_forwarded_for(&self) -> bool {
+        self.views.trust_forwarded_for
     }
 }
 
@@ -80,20 +200,96 @@ async fn init_config() -> Config {
         url: require_env("DATABASE_URL"),
     };
 
-    let s3_config = S3Config {
-        base_url: require_env("S3_BASE_URL"),
-        access_key: require_env("S3_ACCESS_KEY"),
-        secret_key: require_env("S3_SECRET_KEY"),
-        bucket: require_env("S3_BUCKET"),
+    let storage_config = match env::var("STORAGE_BACKEND").as_deref().unwrap_or("s3") {
+        "s3" => StorageConfig::S3(S3Config {
+            base_url: require_env("S3_BASE_URL"),
+            access_key: require_env("S3_ACCESS_KEY"),
+            secret_key: require_env("S3_SECRET_KEY"),
+            bucket: require_env("S3_BUCKET"),
+        }),
+        "local" => StorageConfig::Local {
+            dir: env::var("STORAGE_DIR")
+                .unwrap_or_else(|_| String::from("storage"))
+                .into(),
+        },
+        other => panic!("invalid STORAGE_BACKEND '{other}', expected 's3' or 'local'"),
+    };
+
+    let worker_config = WorkerConfig {
+        enabled: env::var("WORKER_ENABLED")
+            .unwrap_or_else(|_| String::from("true"))
+            .parse::<bool>()
+            .expect("invalid WORKER_ENABLED"),
+    };
+
+    let upload_config = UploadConfig {
+        dir: env::var("UPLOAD_DIR")
+            .unwrap_or_else(|_| String::from("uploads"))
+            .into(),
+        max_size: env::var("UPLOAD_MAX_SIZE")
+            .unwrap_or_else(|_| (16_u64 * 1024 * 1024 * 1024).to_string())
+            .parse::<u64>()
+            .expect("invalid UPLOAD_MAX_SIZE"),
+        expiry_hours: env::var("UPLOAD_EXPIRY_HOURS")
+            .unwrap_or_else(|_| String::from("24"))
+            .parse::<i64>()
+            .expect("invalid UPLOAD_EXPIRY_HOURS"),
+    };
+
+    let trash_config = TrashConfig {
+        retention_days: env::var("TRASH_RETENTION_DAYS")
+            .unwrap_or_else(|_| String::from("30"))
+            .parse::<i64>()
+            .expect("invalid TRASH_RETENTION_DAYS"),
+    };
+
+    let gc_config = GcConfig {
+        grace_hours: env::var("GC_GRACE_HOURS")
+            .unwrap_or_else(|_| String::from("24"))
+            .parse::<i64>()
+            .expect("invalid GC_GRACE_HOURS"),
+        dry_run: env::var("GC_DRY_RUN")
+            .unwrap_or_else(|_| String::from("false"))
+            .parse::<bool>()
+            .expect("invalid GC_DRY_RUN"),
+    };
+
+    let views_config = ViewsConfig {
+        min_watch_seconds: env::var("VIEW_MIN_WATCH_SECONDS")
+            .unwrap_or_else(|_| String::from("30"))
+            .parse::<u64>()
+            .expect("invalid VIEW_MIN_WATCH_SECONDS"),
+        dedup_hours: env::var("VIEW_DEDUP_HOURS")
+            .unwrap_or_else(|_| String::from("24"))
+            .parse::<u64>()
+            .expect("invalid VIEW_DEDUP_HOURS"),
+        flush_seconds: env::var("VIEW_FLUSH_SECONDS")
+            .unwrap_or_else(|_| String::from("10"))
+            .parse::<u64>()
+            .expect("invalid VIEW_FLUSH_SECONDS"),
+        trust_forwarded_for: env::var("VIEW_TRUST_FORWARDED_FOR")
+            .unwrap_or_else(|_| String::from("false"))
+            .parse::<bool>()
+            .expect("invalid VIEW_TRUST_FORWARDED_FOR"),
     };
 
     let jwt_secret = require_env("JWT_SECRET");
+    let playback_secret = env::var("PLAYBACK_SECRET").unwrap_or_else(|_| {
+        tracing::warn!("PLAYBACK_SECRET is not set, deriving it from JWT_SECRET");
+        derive_secret(&jwt_secret, "playback")
+    });
 
     Config {
         server: server_config,
         db: database_config,
-        s3: s3_config,
+        storage: storage_config,
+        worker: worker_config,
+        upload: upload_config,
+        trash: trash_config,
+        gc: gc_config,
+        views: views_config,
         jwt_secret,
+        playback_secret,
     }
 }
 
@@ -101,6 +297,16 @@ pub async fn config() -> &'static Config {
     CONFIG.get_or_init(init_config).await
 }
 
+/// A secret for `purpose` which doesn't reveal `secret`, so that a leaked
+/// playback signature can't be used to forge sessions
+fn derive_secret(secret: &str, purpose: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes a key of any size");
+    mac.update(purpose.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
 fn require_env(var_name: &str) -> String {
     env::var(var_name).unwrap_or_else(|_| panic!("{var_name} is not set"))
 }
@@ -117,8 +117,10 @@ pub async fn me(
 ) -> Result<Json<models::UserWithVideos>, (StatusCode, String)> {
     let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
 
+    // Deleted videos are listed by `GET /videos/trash`
     let related_videos = models::Video::belonging_to(&user)
         .select(models::Video::as_select())
+        .filter(schema::videos::deleted_at.is_null())
         .load(&mut conn)
         .await
         .map_err(errors::internal_error)?;
@@ -0,0 +1,599 @@
+//! Comments on a video under `/videos/:id/comments`. A comment is either on
+//! the video itself or a reply to such a comment, replies can't be replied to.
+
+use crate::{auth, errors, models, pagination, schema, AppState};
+
+use errors::NotFoundExt;
+
+use std::collections::HashMap;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, patch, post, put};
+use axum::{Extension, Json, Router};
+
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use serde::Deserialize;
+
+const MAX_CONTENT_LENGTH: usize = 10_000;
+
+/// Likes of the comment in the current row, for sorting by popularity
+const LIKE_COUNT_SQL: &str = "(select count(*) from comment_likes \
+     where comment_likes.comment_id = comments.id and comment_likes.is_liking)";
+
+pub fn router<S>(state: AppState) -> Router<S> {
+    Router::new()
+        .route("/", get(list_comments))
+        .route(
+            "/",
+            post(create_comment).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/:comment_id",
+            patch(update_comment).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/:comment_id",
+            delete(delete_comment).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route("/:comment_id/replies", get(list_replies))
+        .route(
+            "/:comment_id/like",
+            post(like_comment).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/:comment_id/pin",
+            put(pin_comment).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/:comment_id/pin",
+            delete(unpin_comment).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .with_state(state)
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CommentSort {
+    #[default]
+    Newest,
+    /// Most liked first
+    Top,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListCommentsQuery {
+    #[serde(default)]
+    sort: CommentSort,
+}
+
+/// Comments on the video itself, the pinned one on top of the first page
+async fn list_comments(
+    State(state): State<AppState>,
+    Path(target_video_id): Path<i32>,
+    auth::OptionalUser(logged_user): auth::OptionalUser,
+    Query(params): Query<ListCommentsQuery>,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<Json<pagination::Page<models::CommentWithAuthor>>, (StatusCode, String)> {
+    use schema::comments::dsl::{comments, created_at, id, parent_id, video_id};
+    use schema::users::dsl::users;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = find_video(&mut conn, target_video_id, logged_user.as_ref()).await?;
+
+    let limit = page.limit();
+
+    let mut query = comments
+        .inner_join(users)
+        .select((models::Comment::as_select(), models::User::as_select()))
+        .filter(video_id.eq(target_video.id))
+        .filter(parent_id.is_null())
+        .limit(limit + 1)
+        .into_boxed();
+
+    if let Some(pinned_comment_id) = target_video.pinned_comment_id {
+        query = query.filter(id.ne(pinned_comment_id));
+    }
+
+    query = match params.sort {
+        CommentSort::Newest => {
+            if let Some((last_created_at, last_id)) =
+                page.cursor::<(chrono::DateTime<chrono::Utc>, i32)>()?
+            {
+                query = query.filter(
+                    created_at
+                        .lt(last_created_at)
+                        .or(created_at.eq(last_created_at).and(id.lt(last_id))),
+                );
+            }
+
+            query.order((created_at.desc(), id.desc()))
+        }
+        CommentSort::Top => {
+            if let Some((last_likes, last_created_at, last_id)) =
+                page.cursor::<(i64, chrono::DateTime<chrono::Utc>, i32)>()?
+            {
+                // Integers can't inject anything, the date is bound
+                query = query.filter(
+                    diesel::dsl::sql::<diesel::sql_types::Bool>(&format!(
+                        "({LIKE_COUNT_SQL}, comments.created_at, comments.id) < ({last_likes}, "
+                    ))
+                    .bind::<diesel::sql_types::Timestamptz, _>(last_created_at)
+                    .sql(&format!(", {last_id})")),
+                );
+            }
+
+            query
+                .order(diesel::dsl::sql::<diesel::sql_types::BigInt>(LIKE_COUNT_SQL).desc())
+                .then_order_by((created_at.desc(), id.desc()))
+        }
+    };
+
+    let rows = query
+        .load::<(models::Comment, models::User)>(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let comments_with_author = with_counts(&mut conn, rows)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let mut comments_page = match params.sort {
+        CommentSort::Newest => {
+            pagination::Page::from_rows(comments_with_author, limit, |comment| {
+                (comment.comment.created_at, comment.comment.id)
+            })
+        }
+        CommentSort::Top => pagination::Page::from_rows(comments_with_author, limit, |comment| {
+            (
+                comment.likes,
+                comment.comment.created_at,
+                comment.comment.id,
+            )
+        }),
+    };
+
+    if let (None, Some(pinned_comment_id)) = (&page.cursor, target_video.pinned_comment_id) {
+        let pinned = comments
+            .inner_join(users)
+            .select((models::Comment::as_select(), models::User::as_select()))
+            .filter(id.eq(pinned_comment_id))
+            .load::<(models::Comment, models::User)>(&mut conn)
+            .await
+            .map_err(errors::internal_error)?;
+
+        let pinned = with_counts(&mut conn, pinned)
+            .await
+            .map_err(errors::internal_error)?;
+
+        comments_page.items.splice(0..0, pinned);
+    }
+
+    Ok(Json(comments_page))
+}
+
+/// Replies to a comment, oldest first so that they read like a conversation
+async fn list_replies(
+    State(state): State<AppState>,
+    Path((target_video_id, comment_id)): Path<(i32, i32)>,
+    auth::OptionalUser(logged_user): auth::OptionalUser,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<Json<pagination::Page<models::CommentWithAuthor>>, (StatusCode, String)> {
+    use schema::comments::dsl::{comments, created_at, id, parent_id};
+    use schema::users::dsl::users;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = find_video(&mut conn, target_video_id, logged_user.as_ref()).await?;
+    let parent = find_comment(&mut conn, target_video.id, comment_id).await?;
+
+    let limit = page.limit();
+
+    let mut query = comments
+        .inner_join(users)
+        .select((models::Comment::as_select(), models::User::as_select()))
+        .filter(parent_id.eq(parent.id))
+        .order((created_at.asc(), id.asc()))
+        .limit(limit + 1)
+        .into_boxed();
+
+    if let Some((last_created_at, last_id)) =
+        page.cursor::<(chrono::DateTime<chrono::Utc>, i32)>()?
+    {
+        query = query.filter(
+            created_at
+                .gt(last_created_at)
+                .or(created_at.eq(last_created_at).and(id.gt(last_id))),
+        );
+    }
+
+    let rows = query
+        .load::<(models::Comment, models::User)>(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let replies_page =
+        pagination::Page::from_rows(rows, limit, |(reply, _)| (reply.created_at, reply.id));
+
+    let replies = with_counts(&mut conn, replies_page.items)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(Json(pagination::Page {
+        items: replies,
+        next_cursor: replies_page.next_cursor,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateCommentBody {
+    content: String,
+
+    /// Comment to reply to
+    parent_id: Option<i32>,
+}
+
+async fn create_comment(
+    State(state): State<AppState>,
+    Path(target_video_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<CreateCommentBody>,
+) -> Result<(StatusCode, Json<models::CommentWithAuthor>), (StatusCode, String)> {
+    use schema::comments::dsl::{author_id, comments, content, parent_id, video_id};
+
+    let new_content = validate_content(&body.content)?;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = find_video(&mut conn, target_video_id, Some(&logged_user)).await?;
+
+    if !target_video.comments_enabled {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Comments are disabled on this video".to_string(),
+        ));
+    }
+
+    if let Some(target_parent_id) = body.parent_id {
+        let parent = find_comment(&mut conn, target_video.id, target_parent_id).await?;
+
+        if parent.parent_id.is_some() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Cannot reply to a reply".to_string(),
+            ));
+        }
+    }
+
+    let inserted_comment = diesel::insert_into(comments)
+        .values((
+            video_id.eq(target_video.id),
+            author_id.eq(logged_user.id),
+            parent_id.eq(body.parent_id),
+            content.eq(new_content),
+        ))
+        .returning(models::Comment::as_returning())
+        .get_result(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(models::CommentWithAuthor {
+            comment: inserted_comment,
+            author: logged_user,
+            likes: 0,
+            dislikes: 0,
+            reply_count: 0,
+        }),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateCommentBody {
+    content: String,
+}
+
+async fn update_comment(
+    State(state): State<AppState>,
+    Path((target_video_id, comment_id)): Path<(i32, i32)>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<UpdateCommentBody>,
+) -> Result<Json<models::CommentWithAuthor>, (StatusCode, String)> {
+    use schema::comments::dsl::{comments, content};
+
+    let new_content = validate_content(&body.content)?;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = find_video(&mut conn, target_video_id, Some(&logged_user)).await?;
+    let target_comment = find_comment(&mut conn, target_video.id, comment_id).await?;
+
+    if target_comment.author_id != logged_user.id {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    let updated_comment = diesel::update(comments.find(target_comment.id))
+        .set(content.eq(new_content))
+        .returning(models::Comment::as_returning())
+        .get_result(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let mut updated = with_counts(&mut conn, vec![(updated_comment, logged_user)])
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(Json(updated.remove(0)))
+}
+
+/// Comments can be deleted by their author and by the author of the video,
+/// along with their replies
+async fn delete_comment(
+    State(state): State<AppState>,
+    Path((target_video_id, comment_id)): Path<(i32, i32)>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::comments::dsl::comments;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = find_video(&mut conn, target_video_id, Some(&logged_user)).await?;
+    let target_comment = find_comment(&mut conn, target_video.id, comment_id).await?;
+
+    if target_comment.author_id != logged_user.id && target_video.author_id != logged_user.id {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    diesel::delete(comments.find(target_comment.id))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct LikeCommentBody {
+    likes: Option<bool>,
+}
+
+async fn like_comment(
+    State(state): State<AppState>,
+    Path((target_video_id, target_comment_id)): Path<(i32, i32)>,
+    Extension(logged_user): Extension<models::User>,
+    Json(like_comment_query): Json<LikeCommentBody>,
+) -> Result<(), (StatusCode, String)> {
+    use schema::comment_likes::dsl::{comment_id, comment_likes, is_liking, user_id};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = find_video(&mut conn, target_video_id, Some(&logged_user)).await?;
+    find_comment(&mut conn, target_video.id, target_comment_id).await?;
+
+    if let Some(new_like) = like_comment_query.likes {
+        let existing_like = comment_likes
+            .select(models::CommentLike::as_select())
+            .filter(comment_id.eq(target_comment_id))
+            .filter(user_id.eq(logged_user.id))
+            .first(&mut conn)
+            .await
+            .optional()
+            .map_err(errors::internal_error)?;
+
+        if existing_like.is_none() {
+            diesel::insert_into(comment_likes)
+                .values((
+                    comment_id.eq(target_comment_id),
+                    user_id.eq(logged_user.id),
+                    is_liking.eq(new_like),
+                ))
+                .execute(&mut conn)
+                .await
+                .map_err(errors::internal_error)?;
+        } else {
+            diesel::update(comment_likes)
+                .filter(comment_id.eq(target_comment_id))
+                .filter(user_id.eq(logged_user.id))
+                .set(is_liking.eq(new_like))
+                .execute(&mut conn)
+                .await
+                .map_err(errors::internal_error)?;
+        }
+    } else {
+        diesel::delete(comment_likes)
+            .filter(user_id.eq(logged_user.id))
+            .filter(comment_id.eq(target_comment_id))
+            .execute(&mut conn)
+            .await
+            .map_err(errors::internal_error)?;
+    }
+
+    Ok(())
+}
+
+/// Let the author of the video put a comment on top, replacing the pinned one
+async fn pin_comment(
+    State(state): State<AppState>,
+    Path((target_video_id, comment_id)): Path<(i32, i32)>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::videos::dsl::{pinned_comment_id, videos};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = find_video(&mut conn, target_video_id, Some(&logged_user)).await?;
+
+    if target_video.author_id != logged_user.id {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    let target_comment = find_comment(&mut conn, target_video.id, comment_id).await?;
+
+    if target_comment.parent_id.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Replies cannot be pinned".to_string(),
+        ));
+    }
+
+    diesel::update(videos.find(target_video.id))
+        .set(pinned_comment_id.eq(target_comment.id))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn unpin_comment(
+    State(state): State<AppState>,
+    Path((target_video_id, comment_id)): Path<(i32, i32)>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::videos::dsl::{pinned_comment_id, videos};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = find_video(&mut conn, target_video_id, Some(&logged_user)).await?;
+
+    if target_video.author_id != logged_user.id {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    if target_video.pinned_comment_id != Some(comment_id) {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    diesel::update(videos.find(target_video.id))
+        .set(pinned_comment_id.eq(None::<i32>))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn validate_content(content: &str) -> Result<String, (StatusCode, String)> {
+    let content = content.trim();
+
+    if content.is_empty() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "content: must not be empty".to_string(),
+        ));
+    }
+
+    if content.chars().count() > MAX_CONTENT_LENGTH {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("content: must be at most {MAX_CONTENT_LENGTH} characters"),
+        ));
+    }
+
+    Ok(content.to_string())
+}
+
+/// A video `user` can see, whose comments they can therefore see too
+async fn find_video(
+    conn: &mut AsyncPgConnection,
+    target_video_id: i32,
+    user: Option<&models::User>,
+) -> Result<models::Video, (StatusCode, String)> {
+    use schema::videos::dsl::{deleted_at, videos};
+
+    videos
+        .select(models::Video::as_select())
+        .find(target_video_id)
+        .filter(deleted_at.is_null())
+        .first(conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|video| video.is_visible_to(user))
+        .map_not_found()
+}
+
+async fn find_comment(
+    conn: &mut AsyncPgConnection,
+    target_video_id: i32,
+    comment_id: i32,
+) -> Result<models::Comment, (StatusCode, String)> {
+    use schema::comments::dsl::{comments, video_id};
+
+    comments
+        .select(models::Comment::as_select())
+        .find(comment_id)
+        .filter(video_id.eq(target_video_id))
+        .first(conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .map_not_found()
+}
+
+/// Attach like, dislike and reply counts to comments, in two queries
+async fn with_counts(
+    conn: &mut AsyncPgConnection,
+    rows: Vec<(models::Comment, models::User)>,
+) -> QueryResult<Vec<models::CommentWithAuthor>> {
+    use diesel::dsl::count_star;
+    use schema::comment_likes::dsl::{comment_id, comment_likes, is_liking};
+    use schema::comments::dsl::{comments, parent_id};
+
+    let ids = rows
+        .iter()
+        .map(|(comment, _)| comment.id)
+        .collect::<Vec<_>>();
+
+    let like_counts = comment_likes
+        .filter(comment_id.eq_any(&ids))
+        .group_by((comment_id, is_liking))
+        .select((comment_id, is_liking, count_star()))
+        .load::<(i32, bool, i64)>(conn)
+        .await?
+        .into_iter()
+        .map(|(id, liking, count)| ((id, liking), count))
+        .collect::<HashMap<_, _>>();
+
+    let reply_counts = comments
+        .filter(parent_id.assume_not_null().eq_any(&ids))
+        .group_by(parent_id)
+        .select((parent_id.assume_not_null(), count_star()))
+        .load::<(i32, i64)>(conn)
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    Ok(rows
+        .into_iter()
+        .map(|(comment, author)| models::CommentWithAuthor {
+            likes: like_counts.get(&(comment.id, true)).copied().unwrap_or(0),
+            dislikes: like_counts.get(&(comment.id, false)).copied().unwrap_or(0),
+            reply_count: reply_counts.get(&comment.id).copied().unwrap_or(0),
+            comment,
+            author,
+        })
+        .collect())
+}
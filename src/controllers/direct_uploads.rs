@@ -0,0 +1,354 @@
+//! Uploads sent by the client straight to the object store using presigned
+//! S3 multipart upload URLs, so the video never goes through the API. Only
+//! available with the S3 storage backend.
+//!
+//! 1. `POST /videos/direct-uploads` starts the multipart upload and returns a
+//!    presigned `PUT` URL per part
+//! 2. the client uploads every part and keeps the `ETag` of each response
+//! 3. `POST /videos/direct-uploads/:id/complete` assembles the parts, then the
+//!    video is validated and queued for processing like any other upload
+//!
+//! Uploads which are never completed nor aborted by the client are aborted by
+//! `abort_expired`, so that S3 drops their parts.
+
+use crate::{auth, config, errors, ingest, models, schema, storage, AppState};
+
+use errors::NotFoundExt;
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{delete, post};
+use axum::{Extension, Json, Router};
+
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+
+use serde::{Deserialize, Serialize};
+
+/// S3 refuses parts smaller than this, except for the last one
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+const DEFAULT_PART_SIZE: u64 = 64 * 1024 * 1024;
+
+/// S3 limit on the number of parts of an upload
+const MAX_PARTS: u64 = 10_000;
+
+const PART_URL_EXPIRY_SECS: u32 = 6 * 60 * 60;
+
+/// How long ffmpeg may read the assembled object for validation
+const VALIDATION_URL_EXPIRY_SECS: u32 = 10 * 60;
+
+pub fn router<S>(state: AppState) -> Router<S> {
+    Router::new()
+        .route("/", post(create_upload))
+        .route("/:id/complete", post(complete_upload))
+        .route("/:id", delete(abort_upload))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth::middleware,
+        ))
+        .with_state(state)
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateUploadBody {
+    title: String,
+    description: String,
+
+    /// Size of the whole file in bytes
+    size: u64,
+
+    #[serde(default)]
+    visibility: models::Visibility,
+}
+
+#[derive(Debug, Serialize)]
+struct PresignedPart {
+    part_number: u32,
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedUpload {
+    id: uuid::Uuid,
+    part_size: u64,
+    parts: Vec<PresignedPart>,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+async fn create_upload(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<CreateUploadBody>,
+) -> Result<Json<CreatedUpload>, (StatusCode, String)> {
+    use schema::multipart_uploads::dsl::{
+        description, id, multipart_uploads, part_size, s3_upload_id, size, title, user_id,
+        visibility,
+    };
+
+    ingest::validate_details(Some(&body.title), Some(&body.description))?;
+
+    if body.size == 0 {
+        return Err((StatusCode::BAD_REQUEST, "The file is empty".to_string()));
+    }
+
+    if body.size > config::config().await.upload_max_size() {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "The upload exceeds the maximum size".to_string(),
+        ));
+    }
+
+    let upload_part_size = DEFAULT_PART_SIZE
+        .max(body.size.div_ceil(MAX_PARTS))
+        .max(MIN_PART_SIZE);
+    let part_count = body.size.div_ceil(upload_part_size) as u32;
+
+    // The object is created right where `ingest` would have put it
+    let bucket_id = uuid::Uuid::new_v4();
+    let key = bucket_id.to_string();
+
+    let multipart_upload_id = state
+        .storage
+        .create_multipart_upload(&key)
+        .await
+        .map_err(|err| (err.status_code(), err.to_string()))?;
+
+    let mut parts = Vec::with_capacity(part_count as usize);
+
+    for part_number in 1..=part_count {
+        let url = state
+            .storage
+            .presign_upload_part(
+                &key,
+                &multipart_upload_id,
+                part_number,
+                PART_URL_EXPIRY_SECS,
+            )
+            .await
+            .map_err(errors::internal_error)?;
+
+        parts.push(PresignedPart { part_number, url });
+    }
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    diesel::insert_into(multipart_uploads)
+        .values((
+            id.eq(bucket_id),
+            user_id.eq(logged_user.id),
+            s3_upload_id.eq(&multipart_upload_id),
+            title.eq(body.title),
+            description.eq(body.description),
+            size.eq(body.size as i64),
+            part_size.eq(upload_part_size as i64),
+            visibility.eq(body.visibility),
+        ))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(Json(CreatedUpload {
+        id: bucket_id,
+        part_size: upload_part_size,
+        parts,
+        expires_at: chrono::Utc::now() + chrono::Duration::seconds(PART_URL_EXPIRY_SECS.into()),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadedPart {
+    part_number: u32,
+    etag: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompleteUploadBody {
+    parts: Vec<UploadedPart>,
+}
+
+async fn complete_upload(
+    State(state): State<AppState>,
+    Path(upload_id): Path<uuid::Uuid>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<CompleteUploadBody>,
+) -> Result<Json<models::Video>, (StatusCode, String)> {
+    use schema::multipart_uploads::dsl::multipart_uploads;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let upload = find_upload(&mut conn, upload_id, &logged_user).await?;
+
+    drop(conn);
+
+    let key = upload.id.to_string();
+
+    let mut parts = body
+        .parts
+        .into_iter()
+        .map(|part| storage::Part {
+            part_number: part.part_number,
+            etag: part.etag,
+        })
+        .collect::<Vec<_>>();
+    parts.sort_by_key(|part| part.part_number);
+
+    // Missing or mismatched parts are the client's fault, the upload itself
+    // stays open so it can try again
+    state
+        .storage
+        .complete_multipart_upload(&key, &upload.s3_upload_id, parts)
+        .await
+        .map_err(|err| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Cannot complete the upload: {err}"),
+            )
+        })?;
+
+    let head = state
+        .storage
+        .head(&key)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let validated_video = if head.size != upload.size as u64 {
+        Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The uploaded file does not have the announced size".to_string(),
+        ))
+    } else {
+        // ffmpeg reads the object over HTTP instead of downloading all of it
+        let url = state
+            .storage
+            .presign_get(&key, VALIDATION_URL_EXPIRY_SECS)
+            .await
+            .map_err(errors::internal_error)?;
+
+        ingest::validate(PathBuf::from(url)).await
+    };
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    // Whatever happens next, the multipart upload is over
+    diesel::delete(multipart_uploads.find(upload.id))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    drop(conn);
+
+    let validated_video = match validated_video {
+        Ok(validated_video) => validated_video,
+        Err(err) => {
+            if let Err(delete_err) = state.storage.delete(&key).await {
+                tracing::warn!(%upload_id, "cannot delete rejected upload: {delete_err}");
+            }
+
+            return Err(err);
+        }
+    };
+
+    let new_upload = ingest::NewUpload {
+        title: upload.title,
+        description: upload.description,
+        author_id: upload.user_id,
+        visibility: upload.visibility,
+    };
+
+    let inserted_video =
+        ingest::create_video(&state, new_upload, upload.id, validated_video).await?;
+
+    Ok(Json(inserted_video))
+}
+
+async fn abort_upload(
+    State(state): State<AppState>,
+    Path(upload_id): Path<uuid::Uuid>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::multipart_uploads::dsl::multipart_uploads;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let upload = find_upload(&mut conn, upload_id, &logged_user).await?;
+
+    state
+        .storage
+        .abort_multipart_upload(&upload.id.to_string(), &upload.s3_upload_id)
+        .await
+        .map_err(errors::internal_error)?;
+
+    diesel::delete(multipart_uploads.find(upload.id))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Abort the multipart uploads started before the expiry, which can't be
+/// shorter than the validity of the part URLs. Returns how many were.
+pub async fn abort_expired(state: &AppState) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    use schema::multipart_uploads::dsl::{created_at, multipart_uploads};
+
+    let expiry = config::config()
+        .await
+        .upload_expiry()
+        .max(chrono::Duration::seconds(PART_URL_EXPIRY_SECS.into()));
+    let expired_before = chrono::Utc::now() - expiry;
+
+    let mut conn = state.db_pool.get().await?;
+
+    let expired = multipart_uploads
+        .select(models::MultipartUpload::as_select())
+        .filter(created_at.lt(expired_before))
+        .load(&mut conn)
+        .await?;
+
+    let mut aborted = 0;
+
+    for upload in expired {
+        match state
+            .storage
+            .abort_multipart_upload(&upload.id.to_string(), &upload.s3_upload_id)
+            .await
+        {
+            // Already gone on the S3 side, only the row is left
+            Ok(()) | Err(storage::StorageError::NotFound) => {}
+            Err(err) => {
+                tracing::warn!(upload_id = %upload.id, "cannot abort expired upload: {err}");
+                continue;
+            }
+        }
+
+        diesel::delete(multipart_uploads.find(upload.id))
+            .execute(&mut conn)
+            .await?;
+
+        aborted += 1;
+    }
+
+    Ok(aborted)
+}
+
+async fn find_upload(
+    conn: &mut diesel_async::AsyncPgConnection,
+    upload_id: uuid::Uuid,
+    logged_user: &models::User,
+) -> Result<models::MultipartUpload, (StatusCode, String)> {
+    use schema::multipart_uploads::dsl::{multipart_uploads, user_id};
+
+    multipart_uploads
+        .find(upload_id)
+        .filter(user_id.eq(logged_user.id))
+        .select(models::MultipartUpload::as_select())
+        .first(conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .map_not_found()
+}
@@ -0,0 +1,83 @@
+use crate::{auth, errors, models, pagination, schema, AppState};
+
+use super::videos;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+
+pub fn router<S>(state: AppState) -> Router<S> {
+    Router::new()
+        .route("/subscriptions", get(subscriptions_feed))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth::middleware,
+        ))
+        .with_state(state)
+}
+
+/// Latest public videos of the channels the logged user subscribed to
+async fn subscriptions_feed(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    Query(params): Query<pagination::PageQuery>,
+) -> Result<Json<pagination::Page<models::VideoWithAuthor>>, (StatusCode, String)> {
+    use schema::subscriptions::dsl::{channel_id, subscriber_id, subscriptions};
+    use schema::users::dsl::users;
+    use schema::video_stats;
+    use schema::videos::dsl::{author_id, deleted_at, id, published_at, status, visibility};
+
+    let limit = params.limit();
+    let cursor = params.cursor::<(chrono::DateTime<chrono::Utc>, i32)>()?;
+
+    let followed_channels = subscriptions
+        .select(channel_id)
+        .filter(subscriber_id.eq(logged_user.id));
+
+    let mut query = schema::videos::table
+        .inner_join(users)
+        .left_join(video_stats::table)
+        .select((
+            models::VIDEO_ALL_COLUMNS,
+            models::User::as_select(),
+            Option::<models::VideoStats>::as_select(),
+        ))
+        .filter(author_id.eq_any(followed_channels))
+        .filter(status.eq(models::VideoStatus::Ready))
+        .filter(deleted_at.is_null())
+        .filter(visibility.eq(models::Visibility::Public))
+        .order((published_at.desc(), id.desc()))
+        .limit(limit + 1)
+        .into_boxed();
+
+    if let Some((last_published_at, last_id)) = cursor {
+        query = query.filter(
+            published_at
+                .lt(last_published_at)
+                .or(published_at.eq(last_published_at).and(id.lt(last_id))),
+        );
+    }
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let rows = query
+        .load::<(models::Video, models::User, Option<models::VideoStats>)>(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let page =
+        pagination::Page::from_rows(rows, limit, |(video, _, _)| (video.published_at, video.id));
+
+    let items = videos::videos_with_author(&mut conn, page.items, Some(&logged_user))
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(Json(pagination::Page {
+        items,
+        next_cursor: page.next_cursor,
+    }))
+}
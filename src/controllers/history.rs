@@ -0,0 +1,247 @@
+//! What the logged user watched and where they stopped, fed by the player
+//! through `POST /videos/:id/progress`, which the videos router mounts
+
+use crate::{auth, errors, models, pagination, schema, AppState};
+
+use super::videos;
+
+use errors::NotFoundExt;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, put};
+use axum::{Extension, Json, Router};
+
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+
+use serde::Deserialize;
+
+pub fn router<S>(state: AppState) -> Router<S> {
+    Router::new()
+        .route("/me/history", get(list_history).delete(clear_history))
+        .route("/me/history/paused", put(set_history_paused))
+        .route("/me/history/:video_id", delete(delete_history_entry))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth::middleware,
+        ))
+        .with_state(state)
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ProgressBody {
+    position_seconds: i64,
+}
+
+/// Heartbeat of the player, remembering where the user is in the video
+pub(super) async fn record_progress(
+    State(state): State<AppState>,
+    Path(target_video_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<ProgressBody>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::videos::dsl::videos;
+    use schema::watch_history::dsl::{
+        position_seconds, user_id, video_id, watch_history, watched_at,
+    };
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = videos
+        .select(models::Video::as_select())
+        .find(target_video_id)
+        .filter(schema::videos::deleted_at.is_null())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|video| video.is_visible_to(Some(&logged_user)))
+        .map_not_found()?;
+
+    if logged_user.history_paused {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    // Players may report a little past the end
+    let position = body
+        .position_seconds
+        .clamp(0, target_video.duration_seconds.max(0));
+
+    diesel::insert_into(watch_history)
+        .values((
+            user_id.eq(logged_user.id),
+            video_id.eq(target_video.id),
+            position_seconds.eq(position),
+        ))
+        .on_conflict((user_id, video_id))
+        .do_update()
+        .set((
+            position_seconds.eq(position),
+            watched_at.eq(diesel::dsl::now),
+        ))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Watched videos, most recently watched first
+async fn list_history(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<Json<models::History>, (StatusCode, String)> {
+    use schema::video_stats;
+    use schema::videos::dsl::{author_id, deleted_at, visibility};
+    use schema::watch_history::dsl::{
+        position_seconds, user_id, video_id, watch_history, watched_at,
+    };
+
+    let limit = page.limit();
+    let cursor = page.cursor::<(chrono::DateTime<chrono::Utc>, i32)>()?;
+
+    let mut query = watch_history
+        .inner_join(
+            schema::videos::table
+                .inner_join(schema::users::table)
+                .left_join(video_stats::table),
+        )
+        .select((
+            (position_seconds, watched_at),
+            models::VIDEO_ALL_COLUMNS,
+            models::User::as_select(),
+            Option::<models::VideoStats>::as_select(),
+        ))
+        .filter(user_id.eq(logged_user.id))
+        .filter(deleted_at.is_null())
+        .filter(
+            visibility
+                .ne(models::Visibility::Private)
+                .or(author_id.eq(logged_user.id)),
+        )
+        .order((watched_at.desc(), video_id.desc()))
+        .limit(limit + 1)
+        .into_boxed();
+
+    if let Some((last_watched_at, last_video_id)) = cursor {
+        query = query.filter(
+            watched_at.lt(last_watched_at).or(watched_at
+                .eq(last_watched_at)
+                .and(video_id.lt(last_video_id))),
+        );
+    }
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let rows = query
+        .load::<(
+            (i64, chrono::DateTime<chrono::Utc>),
+            models::Video,
+            models::User,
+            Option<models::VideoStats>,
+        )>(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let history_page =
+        pagination::Page::from_rows(rows, limit, |((_, entry_watched_at), video, _, _)| {
+            (*entry_watched_at, video.id)
+        });
+
+    let (progress, rows): (Vec<_>, Vec<_>) = history_page
+        .items
+        .into_iter()
+        .map(|(progress, video, author, stats)| (progress, (video, author, stats)))
+        .unzip();
+
+    let entries = videos::videos_with_author(&mut conn, rows, Some(&logged_user))
+        .await
+        .map_err(errors::internal_error)?
+        .into_iter()
+        .zip(progress)
+        .map(|(video, (position, entry_watched_at))| {
+            let watched_fraction = if video.video.duration_seconds > 0 {
+                (position as f64 / video.video.duration_seconds as f64).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+
+            models::HistoryEntry {
+                video,
+                position_seconds: position,
+                watched_fraction,
+                watched_at: entry_watched_at,
+            }
+        })
+        .collect();
+
+    Ok(Json(models::History {
+        paused: logged_user.history_paused,
+        entries: pagination::Page {
+            items: entries,
+            next_cursor: history_page.next_cursor,
+        },
+    }))
+}
+
+async fn delete_history_entry(
+    State(state): State<AppState>,
+    Path(target_video_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::watch_history::dsl::{user_id, video_id, watch_history};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    diesel::delete(watch_history)
+        .filter(user_id.eq(logged_user.id))
+        .filter(video_id.eq(target_video_id))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn clear_history(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::watch_history::dsl::{user_id, watch_history};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    diesel::delete(watch_history)
+        .filter(user_id.eq(logged_user.id))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryPausedBody {
+    paused: bool,
+}
+
+/// Stop or resume recording history, what is already recorded stays
+async fn set_history_paused(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<HistoryPausedBody>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::users::dsl::{history_paused, users};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    diesel::update(users.find(logged_user.id))
+        .set(history_paused.eq(body.paused))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
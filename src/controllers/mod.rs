@@ -1,2 +1,10 @@
 pub mod auth;
+pub mod comments;
+pub mod direct_uploads;
+pub mod feed;
+pub mod history;
+pub mod playlists;
+pub mod profile;
+pub mod uploads;
+pub mod users;
 pub mod videos;
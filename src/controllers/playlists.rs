@@ -0,0 +1,750 @@
+//! Playlists under `/playlists`. Besides the ones they make, every user has a
+//! "Watch later" playlist, stored like the others once they add a video to
+//! it, and a "Liked videos" one which is read from their likes.
+
+use crate::{auth, errors, models, pagination, schema, AppState};
+
+use super::videos;
+
+use errors::NotFoundExt;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, patch, post, put};
+use axum::{Extension, Json, Router};
+
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+
+use serde::Deserialize;
+
+const MAX_TITLE_LENGTH: usize = 150;
+const MAX_DESCRIPTION_LENGTH: usize = 5000;
+const MAX_ITEMS: i64 = 5000;
+
+const WATCH_LATER_TITLE: &str = "Watch later";
+
+pub fn router<S>(state: AppState) -> Router<S> {
+    Router::new()
+        .route(
+            "/",
+            get(list_playlists).post(create_playlist).route_layer(
+                axum::middleware::from_fn_with_state(state.clone(), auth::middleware),
+            ),
+        )
+        .route(
+            "/watch-later",
+            get(get_watch_later).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/watch-later/items",
+            post(add_watch_later_item).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/liked",
+            get(list_liked).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route("/:id", get(get_playlist))
+        .route(
+            "/:id",
+            patch(update_playlist).delete(delete_playlist).route_layer(
+                axum::middleware::from_fn_with_state(state.clone(), auth::middleware),
+            ),
+        )
+        .route(
+            "/:id/items",
+            post(add_item).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/:id/items/:video_id",
+            delete(remove_item).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/:id/items/:video_id/position",
+            put(move_item).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .with_state(state)
+}
+
+/// Every invalid field with the reason, so a form can show them all at once
+fn validate(title: Option<&str>, description: Option<&str>) -> Result<(), (StatusCode, String)> {
+    let mut errors = Vec::new();
+
+    if let Some(title) = title {
+        if title.trim().is_empty() {
+            errors.push("title: must not be empty".to_string());
+        } else if title.chars().count() > MAX_TITLE_LENGTH {
+            errors.push(format!(
+                "title: must be at most {MAX_TITLE_LENGTH} characters"
+            ));
+        }
+    }
+
+    if let Some(description) = description {
+        if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+            errors.push(format!(
+                "description: must be at most {MAX_DESCRIPTION_LENGTH} characters"
+            ));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err((StatusCode::UNPROCESSABLE_ENTITY, errors.join("\n")))
+    }
+}
+
+/// Playlists of the logged user, built-in ones included once created
+async fn list_playlists(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<Json<Vec<models::Playlist>>, (StatusCode, String)> {
+    use schema::playlists::dsl::{created_at, id};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let user_playlists = models::Playlist::belonging_to(&logged_user)
+        .select(models::Playlist::as_select())
+        .order((created_at.desc(), id.desc()))
+        .load(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(Json(user_playlists))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreatePlaylistBody {
+    title: String,
+    description: Option<String>,
+    visibility: Option<models::Visibility>,
+}
+
+async fn create_playlist(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<CreatePlaylistBody>,
+) -> Result<Json<models::Playlist>, (StatusCode, String)> {
+    use schema::playlists::dsl::playlists;
+
+    validate(Some(&body.title), body.description.as_deref())?;
+
+    let new_playlist = models::NewPlaylist {
+        owner_id: logged_user.id,
+        kind: models::PlaylistKind::Custom,
+        title: body.title.trim().to_string(),
+        description: body.description.unwrap_or_default(),
+        visibility: body.visibility.unwrap_or_default(),
+    };
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let created_playlist = diesel::insert_into(playlists)
+        .values(&new_playlist)
+        .returning(models::Playlist::as_returning())
+        .get_result(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(Json(created_playlist))
+}
+
+async fn get_playlist(
+    State(state): State<AppState>,
+    Path(playlist_id): Path<i32>,
+    auth::OptionalUser(logged_user): auth::OptionalUser,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<Json<models::PlaylistWithItems>, (StatusCode, String)> {
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let playlist = find_playlist(&mut conn, playlist_id, logged_user.as_ref()).await?;
+
+    let playlist_with_items =
+        playlist_with_items(&mut conn, playlist, logged_user.as_ref(), &page).await?;
+
+    Ok(Json(playlist_with_items))
+}
+
+/// Items of the "Watch later" playlist, none before the first one is added
+async fn get_watch_later(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<Json<pagination::Page<models::PlaylistItem>>, (StatusCode, String)> {
+    use schema::playlists::dsl::{kind, owner_id, playlists};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let playlist = playlists
+        .select(models::Playlist::as_select())
+        .filter(owner_id.eq(logged_user.id))
+        .filter(kind.eq(models::PlaylistKind::WatchLater))
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?;
+
+    let Some(playlist) = playlist else {
+        return Ok(Json(pagination::Page {
+            items: Vec::new(),
+            next_cursor: None,
+        }));
+    };
+
+    let playlist_with_items =
+        playlist_with_items(&mut conn, playlist, Some(&logged_user), &page).await?;
+
+    Ok(Json(playlist_with_items.items))
+}
+
+/// Videos the logged user liked, most recent like first
+async fn list_liked(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<Json<pagination::Page<models::VideoWithAuthor>>, (StatusCode, String)> {
+    use schema::likes::dsl::{created_at, is_liking, likes, user_id, video_id};
+    use schema::video_stats;
+    use schema::videos::dsl::{author_id, deleted_at, status, visibility};
+
+    let limit = page.limit();
+    let cursor = page.cursor::<(chrono::DateTime<chrono::Utc>, i32)>()?;
+
+    let mut query = likes
+        .inner_join(
+            schema::videos::table
+                .inner_join(schema::users::table)
+                .left_join(video_stats::table),
+        )
+        .select((
+            created_at,
+            models::VIDEO_ALL_COLUMNS,
+            models::User::as_select(),
+            Option::<models::VideoStats>::as_select(),
+        ))
+        .filter(user_id.eq(logged_user.id))
+        .filter(is_liking.eq(true))
+        .filter(status.eq(models::VideoStatus::Ready))
+        .filter(deleted_at.is_null())
+        .filter(
+            visibility
+                .ne(models::Visibility::Private)
+                .or(author_id.eq(logged_user.id)),
+        )
+        .order((created_at.desc(), video_id.desc()))
+        .limit(limit + 1)
+        .into_boxed();
+
+    if let Some((last_liked_at, last_video_id)) = cursor {
+        query = query.filter(
+            created_at
+                .lt(last_liked_at)
+                .or(created_at.eq(last_liked_at).and(video_id.lt(last_video_id))),
+        );
+    }
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let rows = query
+        .load::<(
+            chrono::DateTime<chrono::Utc>,
+            models::Video,
+            models::User,
+            Option<models::VideoStats>,
+        )>(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let liked_page =
+        pagination::Page::from_rows(rows, limit, |(liked_at, video, _, _)| (*liked_at, video.id));
+
+    let rows = liked_page
+        .items
+        .into_iter()
+        .map(|(_, video, author, stats)| (video, author, stats))
+        .collect();
+
+    let items = videos::videos_with_author(&mut conn, rows, Some(&logged_user))
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(Json(pagination::Page {
+        items,
+        next_cursor: liked_page.next_cursor,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdatePlaylistBody {
+    title: Option<String>,
+    description: Option<String>,
+    visibility: Option<models::Visibility>,
+}
+
+async fn update_playlist(
+    State(state): State<AppState>,
+    Path(playlist_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<UpdatePlaylistBody>,
+) -> Result<Json<models::Playlist>, (StatusCode, String)> {
+    use schema::playlists::dsl::playlists;
+
+    validate(body.title.as_deref(), body.description.as_deref())?;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let playlist = find_owned_playlist(&mut conn, playlist_id, &logged_user).await?;
+    ensure_custom(&playlist)?;
+
+    let changes = models::PlaylistChanges {
+        title: body.title.map(|title| title.trim().to_string()),
+        description: body.description,
+        visibility: body.visibility,
+    };
+
+    // Diesel refuses to run an update without anything to set
+    if changes.title.is_none() && changes.description.is_none() && changes.visibility.is_none() {
+        return Ok(Json(playlist));
+    }
+
+    let updated_playlist = diesel::update(playlists.find(playlist.id))
+        .set(&changes)
+        .returning(models::Playlist::as_returning())
+        .get_result(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(Json(updated_playlist))
+}
+
+async fn delete_playlist(
+    State(state): State<AppState>,
+    Path(playlist_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::playlists::dsl::playlists;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let playlist = find_owned_playlist(&mut conn, playlist_id, &logged_user).await?;
+    ensure_custom(&playlist)?;
+
+    diesel::delete(playlists.find(playlist.id))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct AddItemBody {
+    video_id: i32,
+}
+
+/// Append a video to a playlist, adding it twice changes nothing
+async fn add_item(
+    State(state): State<AppState>,
+    Path(target_playlist_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<AddItemBody>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let playlist = find_owned_playlist(&mut conn, target_playlist_id, &logged_user).await?;
+
+    append_video(&mut conn, playlist.id, body.video_id, &logged_user).await
+}
+
+/// Append a video to the "Watch later" playlist, creating it if needed
+async fn add_watch_later_item(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<AddItemBody>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let playlist = watch_later(&mut conn, &logged_user)
+        .await
+        .map_err(errors::internal_error)?;
+
+    append_video(&mut conn, playlist.id, body.video_id, &logged_user).await
+}
+
+/// Append a video `user` can see to one of their playlists
+async fn append_video(
+    conn: &mut AsyncPgConnection,
+    target_playlist_id: i32,
+    target_video_id: i32,
+    user: &models::User,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::playlist_items::dsl::{playlist_id, playlist_items, position, video_id};
+    use schema::videos::dsl::videos;
+
+    let target_video = videos
+        .select(models::Video::as_select())
+        .find(target_video_id)
+        .filter(schema::videos::deleted_at.is_null())
+        .first(conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|video| video.is_visible_to(Some(user)))
+        .map_not_found()?;
+
+    let added = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                lock_playlist(conn, target_playlist_id).await?;
+
+                let item_count = playlist_items
+                    .filter(playlist_id.eq(target_playlist_id))
+                    .count()
+                    .get_result::<i64>(conn)
+                    .await?;
+
+                if item_count >= MAX_ITEMS {
+                    return Ok(false);
+                }
+
+                let last_position = playlist_items
+                    .select(diesel::dsl::max(position))
+                    .filter(playlist_id.eq(target_playlist_id))
+                    .first::<Option<i32>>(conn)
+                    .await?;
+
+                diesel::insert_into(playlist_items)
+                    .values((
+                        playlist_id.eq(target_playlist_id),
+                        video_id.eq(target_video.id),
+                        position.eq(last_position.map_or(0, |last| last + 1)),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .await?;
+
+                Ok(true)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(errors::internal_error)?;
+
+    if !added {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("A playlist holds at most {MAX_ITEMS} videos"),
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove_item(
+    State(state): State<AppState>,
+    Path((target_playlist_id, target_video_id)): Path<(i32, i32)>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::playlist_items::dsl::{playlist_id, playlist_items, video_id};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let playlist = find_owned_playlist(&mut conn, target_playlist_id, &logged_user).await?;
+
+    diesel::delete(playlist_items)
+        .filter(playlist_id.eq(playlist.id))
+        .filter(video_id.eq(target_video_id))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct MoveItemBody {
+    /// 0-based index in the playlist the video moves to, past the end moves it
+    /// last
+    position: i64,
+}
+
+/// Move a video within a playlist, shifting the videos in between by one
+async fn move_item(
+    State(state): State<AppState>,
+    Path((target_playlist_id, target_video_id)): Path<(i32, i32)>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<MoveItemBody>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::playlist_items::dsl::{playlist_id, playlist_items, position, video_id};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let playlist = find_owned_playlist(&mut conn, target_playlist_id, &logged_user).await?;
+
+    let moved = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                lock_playlist(conn, playlist.id).await?;
+
+                let items = playlist_items.filter(playlist_id.eq(playlist.id));
+
+                let Some(current) = items
+                    .select(position)
+                    .filter(video_id.eq(target_video_id))
+                    .first::<i32>(conn)
+                    .await
+                    .optional()?
+                else {
+                    return Ok(false);
+                };
+
+                // Positions have gaps, the target is the position of the item
+                // currently at the requested index
+                let target = match items
+                    .select(position)
+                    .order(position.asc())
+                    .offset(body.position.max(0))
+                    .first::<i32>(conn)
+                    .await
+                    .optional()?
+                {
+                    Some(target) => target,
+                    None => {
+                        items
+                            .select(position)
+                            .order(position.desc())
+                            .first::<i32>(conn)
+                            .await?
+                    }
+                };
+
+                if target < current {
+                    diesel::update(items)
+                        .filter(position.ge(target))
+                        .filter(position.lt(current))
+                        .set(position.eq(position + 1))
+                        .execute(conn)
+                        .await?;
+                } else if target > current {
+                    diesel::update(items)
+                        .filter(position.gt(current))
+                        .filter(position.le(target))
+                        .set(position.eq(position - 1))
+                        .execute(conn)
+                        .await?;
+                }
+
+                diesel::update(items)
+                    .filter(video_id.eq(target_video_id))
+                    .set(position.eq(target))
+                    .execute(conn)
+                    .await?;
+
+                Ok(true)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(errors::internal_error)?;
+
+    if !moved {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn find_playlist(
+    conn: &mut AsyncPgConnection,
+    target_playlist_id: i32,
+    user: Option<&models::User>,
+) -> Result<models::Playlist, (StatusCode, String)> {
+    use schema::playlists::dsl::playlists;
+
+    playlists
+        .select(models::Playlist::as_select())
+        .find(target_playlist_id)
+        .first(conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|playlist| playlist.is_visible_to(user))
+        .map_not_found()
+}
+
+/// The playlist if `user` owns it, 403 if they can only see it
+async fn find_owned_playlist(
+    conn: &mut AsyncPgConnection,
+    target_playlist_id: i32,
+    user: &models::User,
+) -> Result<models::Playlist, (StatusCode, String)> {
+    let playlist = find_playlist(conn, target_playlist_id, Some(user)).await?;
+
+    if playlist.owner_id != user.id {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    Ok(playlist)
+}
+
+fn ensure_custom(playlist: &models::Playlist) -> Result<(), (StatusCode, String)> {
+    if playlist.kind != models::PlaylistKind::Custom {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Built-in playlists can't be changed".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Serialize changes to the items of a playlist until the transaction ends
+async fn lock_playlist(conn: &mut AsyncPgConnection, target_playlist_id: i32) -> QueryResult<()> {
+    use schema::playlists::dsl::{id, playlists};
+
+    playlists
+        .select(id)
+        .find(target_playlist_id)
+        .for_update()
+        .first::<i32>(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// The "Watch later" playlist of `user`, created by the first video added
+async fn watch_later(
+    conn: &mut AsyncPgConnection,
+    user: &models::User,
+) -> QueryResult<models::Playlist> {
+    use schema::playlists::dsl::{kind, owner_id, playlists};
+
+    let new_playlist = models::NewPlaylist {
+        owner_id: user.id,
+        kind: models::PlaylistKind::WatchLater,
+        title: WATCH_LATER_TITLE.to_string(),
+        description: String::new(),
+        visibility: models::Visibility::Private,
+    };
+
+    diesel::insert_into(playlists)
+        .values(&new_playlist)
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .await?;
+
+    playlists
+        .select(models::Playlist::as_select())
+        .filter(owner_id.eq(user.id))
+        .filter(kind.eq(models::PlaylistKind::WatchLater))
+        .first(conn)
+        .await
+}
+
+/// `playlist` with a page of the videos in it which `logged_user` may see
+async fn playlist_with_items(
+    conn: &mut AsyncPgConnection,
+    playlist: models::Playlist,
+    logged_user: Option<&models::User>,
+    page: &pagination::PageQuery,
+) -> Result<models::PlaylistWithItems, (StatusCode, String)> {
+    use schema::playlist_items::dsl::{playlist_id, playlist_items, position};
+    use schema::video_stats;
+    use schema::videos::dsl::{author_id, deleted_at, status, visibility};
+
+    let limit = page.limit();
+    let cursor = page.cursor::<i32>()?;
+
+    let owner = schema::users::table
+        .select(models::User::as_select())
+        .find(playlist.owner_id)
+        .first(conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let mut query = playlist_items
+        .inner_join(
+            schema::videos::table
+                .inner_join(schema::users::table)
+                .left_join(video_stats::table),
+        )
+        .select((
+            position,
+            models::VIDEO_ALL_COLUMNS,
+            models::User::as_select(),
+            Option::<models::VideoStats>::as_select(),
+        ))
+        .filter(playlist_id.eq(playlist.id))
+        .filter(status.eq(models::VideoStatus::Ready))
+        .filter(deleted_at.is_null())
+        .order(position.asc())
+        .limit(limit + 1)
+        .into_boxed();
+
+    query = match logged_user {
+        Some(logged_user) => query.filter(
+            visibility
+                .ne(models::Visibility::Private)
+                .or(author_id.eq(logged_user.id)),
+        ),
+        None => query.filter(visibility.ne(models::Visibility::Private)),
+    };
+
+    if let Some(last_position) = cursor {
+        query = query.filter(position.gt(last_position));
+    }
+
+    let rows = query
+        .load::<(i32, models::Video, models::User, Option<models::VideoStats>)>(conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let items_page =
+        pagination::Page::from_rows(rows, limit, |(item_position, _, _, _)| *item_position);
+
+    let (positions, rows): (Vec<_>, Vec<_>) = items_page
+        .items
+        .into_iter()
+        .map(|(item_position, video, author, stats)| (item_position, (video, author, stats)))
+        .unzip();
+
+    let items = videos::videos_with_author(conn, rows, logged_user)
+        .await
+        .map_err(errors::internal_error)?
+        .into_iter()
+        .zip(positions)
+        .map(|(video, item_position)| models::PlaylistItem {
+            position: item_position,
+            video,
+        })
+        .collect();
+
+    Ok(models::PlaylistWithItems {
+        playlist,
+        owner,
+        items: pagination::Page {
+            items,
+            next_cursor: items_page.next_cursor,
+        },
+    })
+}
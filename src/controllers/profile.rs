@@ -0,0 +1,289 @@
+//! The logged user's profile under `/me`, and the images it links to
+
+use crate::profile_image::{self, ImageKind};
+use crate::{auth, errors, models, schema, video_util, AppState};
+
+use errors::NotFoundExt;
+
+use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, patch, put};
+use axum::{Extension, Json, Router};
+use axum_typed_multipart::{FieldData, TryFromMultipart, TypedMultipart};
+
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use serde::Deserialize;
+use tempfile::NamedTempFile;
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 50;
+const MAX_BIO_LENGTH: usize = 1000;
+const MAX_LINKS: usize = 5;
+const MAX_LINK_LENGTH: usize = 200;
+
+pub fn router<S>(state: AppState) -> Router<S> {
+    Router::new()
+        .route(
+            "/me",
+            patch(update_profile).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/me/avatar",
+            put(upload_avatar)
+                .delete(delete_avatar)
+                .layer(DefaultBodyLimit::disable())
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    auth::middleware,
+                )),
+        )
+        .route(
+            "/me/banner",
+            put(upload_banner)
+                .delete(delete_banner)
+                .layer(DefaultBodyLimit::disable())
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    auth::middleware,
+                )),
+        )
+        .route("/images/:kind/:image/:size", get(get_image))
+        .with_state(state)
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateProfileBody {
+    /// An empty name removes it
+    display_name: Option<String>,
+    bio: Option<String>,
+    links: Option<Vec<String>>,
+}
+
+impl UpdateProfileBody {
+    /// Every invalid field with the reason, so a form can show them all at once
+    fn validate(&self) -> Result<(), (StatusCode, String)> {
+        let mut errors = Vec::new();
+
+        if let Some(display_name) = &self.display_name {
+            if display_name.trim().chars().count() > MAX_DISPLAY_NAME_LENGTH {
+                errors.push(format!(
+                    "display_name: must be at most {MAX_DISPLAY_NAME_LENGTH} characters"
+                ));
+            }
+        }
+
+        if let Some(bio) = &self.bio {
+            if bio.chars().count() > MAX_BIO_LENGTH {
+                errors.push(format!("bio: must be at most {MAX_BIO_LENGTH} characters"));
+            }
+        }
+
+        if let Some(links) = &self.links {
+            if links.len() > MAX_LINKS {
+                errors.push(format!("links: must be at most {MAX_LINKS}"));
+            }
+
+            for link in links {
+                let is_web_url = (link.starts_with("https://") || link.starts_with("http://"))
+                    && !link.contains(char::is_whitespace);
+
+                if !is_web_url {
+                    errors.push(format!("links: '{link}' is not an http(s) URL"));
+                } else if link.len() > MAX_LINK_LENGTH {
+                    errors.push(format!(
+                        "links: must be at most {MAX_LINK_LENGTH} characters each"
+                    ));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err((StatusCode::UNPROCESSABLE_ENTITY, errors.join("\n")))
+        }
+    }
+}
+
+async fn update_profile(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<UpdateProfileBody>,
+) -> Result<Json<models::User>, (StatusCode, String)> {
+    use schema::users::dsl::users;
+
+    body.validate()?;
+
+    let changes = models::ProfileChanges {
+        display_name: body.display_name.map(|display_name| {
+            Some(display_name.trim().to_string()).filter(|display_name| !display_name.is_empty())
+        }),
+        bio: body.bio,
+        links: body
+            .links
+            .map(|links| links.into_iter().map(Some).collect()),
+    };
+
+    // Diesel refuses to run an update without anything to set
+    if changes.display_name.is_none() && changes.bio.is_none() && changes.links.is_none() {
+        return Ok(Json(logged_user));
+    }
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let updated_user = diesel::update(users.find(logged_user.id))
+        .set(&changes)
+        .returning(models::User::as_returning())
+        .get_result(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(Json(updated_user))
+}
+
+#[derive(TryFromMultipart)]
+struct UploadImageRequest {
+    #[form_data(limit = "10MiB")]
+    image: FieldData<NamedTempFile>,
+}
+
+async fn upload_avatar(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    TypedMultipart(upload_request): TypedMultipart<UploadImageRequest>,
+) -> Result<Json<models::User>, (StatusCode, String)> {
+    replace_image(&state, logged_user, ImageKind::Avatar, Some(upload_request)).await
+}
+
+async fn delete_avatar(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<Json<models::User>, (StatusCode, String)> {
+    replace_image(&state, logged_user, ImageKind::Avatar, None).await
+}
+
+async fn upload_banner(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    TypedMultipart(upload_request): TypedMultipart<UploadImageRequest>,
+) -> Result<Json<models::User>, (StatusCode, String)> {
+    replace_image(&state, logged_user, ImageKind::Banner, Some(upload_request)).await
+}
+
+async fn delete_banner(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<Json<models::User>, (StatusCode, String)> {
+    replace_image(&state, logged_user, ImageKind::Banner, None).await
+}
+
+/// Set the avatar or banner of `user` to the uploaded image, or remove it,
+/// then delete the previous one
+async fn replace_image(
+    state: &AppState,
+    user: models::User,
+    kind: ImageKind,
+    upload_request: Option<UploadImageRequest>,
+) -> Result<Json<models::User>, (StatusCode, String)> {
+    let new_image = match upload_request {
+        Some(upload_request) => {
+            let image_path = upload_request.image.contents.path().to_owned();
+
+            let images = tokio::task::spawn_blocking(move || {
+                let frame = video_util::decode_image(image_path).map_err(|_| {
+                    (
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        "The file is not a supported image".to_string(),
+                    )
+                })?;
+
+                profile_image::encode_all(&frame, kind).map_err(errors::internal_error)
+            })
+            .await
+            .map_err(errors::internal_error)??;
+
+            let new_image = profile_image::upload(state.storage.as_ref(), kind, images)
+                .await
+                .map_err(errors::internal_error)?;
+
+            Some(new_image)
+        }
+        None => None,
+    };
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let updated_user = set_image(&mut conn, user.id, kind, new_image)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let old_image = match kind {
+        ImageKind::Avatar => user.avatar,
+        ImageKind::Banner => user.banner,
+    };
+
+    if let Some(old_image) = old_image {
+        if let Err(err) = profile_image::delete(state.storage.as_ref(), kind, old_image).await {
+            tracing::warn!(user_id = user.id, "cannot delete replaced image: {err}");
+        }
+    }
+
+    Ok(Json(updated_user))
+}
+
+async fn set_image(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    kind: ImageKind,
+    image: Option<uuid::Uuid>,
+) -> QueryResult<models::User> {
+    use schema::users::dsl::{avatar, banner, users};
+
+    let target_user = diesel::update(users.find(user_id));
+
+    match kind {
+        ImageKind::Avatar => {
+            target_user
+                .set(avatar.eq(image))
+                .returning(models::User::as_returning())
+                .get_result(conn)
+                .await
+        }
+        ImageKind::Banner => {
+            target_user
+                .set(banner.eq(image))
+                .returning(models::User::as_returning())
+                .get_result(conn)
+                .await
+        }
+    }
+}
+
+async fn get_image(
+    State(state): State<AppState>,
+    Path((prefix, image, size_name)): Path<(String, uuid::Uuid, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let kind = ImageKind::from_prefix(&prefix).map_not_found()?;
+    let size = kind.find_size(&size_name).map_not_found()?;
+
+    // Replaced images are deleted, so whatever is stored is current
+    let data = state
+        .storage
+        .get(&profile_image::key(kind, image, size))
+        .await
+        .map_err(|err| (err.status_code(), err.to_string()))?;
+
+    // An image id is never reused, so the image can be cached forever
+    Ok((
+        [
+            (header::CONTENT_TYPE, crate::thumbnail::CONTENT_TYPE),
+            (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+        ],
+        data,
+    ))
+}
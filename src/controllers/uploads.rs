@@ -0,0 +1,590 @@
+//! Resumable uploads implementing the core tus 1.0 protocol, with the
+//! `creation`, `termination` and `expiration` extensions. See https://tus.io/protocols/resumable-upload
+//!
+//! Received data is appended to a file in the upload directory. Once the last
+//! byte arrives the file goes through the same ingest flow as a regular
+//! upload, and the id of the new video is returned in the `Video-Id` header.
+//!
+//! An upload expires `config::upload_expiry` after the last request writing
+//! to it, and `remove_expired` then deletes its file.
+
+use crate::{auth, config, errors, ingest, models, schema, AppState};
+
+use errors::NotFoundExt;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use axum::body::Body;
+use axum::extract::{Path, Request, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{head, post};
+use axum::{Extension, Router};
+
+use base64::Engine;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use futures::StreamExt;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,termination,expiration";
+
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+const UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+const VIDEO_ID: HeaderName = HeaderName::from_static("video-id");
+
+pub fn router<S>(state: AppState) -> Router<S> {
+    Router::new()
+        .route(
+            "/",
+            post(create_upload)
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    auth::middleware,
+                ))
+                .options(describe_server),
+        )
+        .route(
+            "/:id",
+            head(get_offset)
+                .patch(append_chunk)
+                .delete(terminate_upload)
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    auth::middleware,
+                )),
+        )
+        .layer(axum::middleware::from_fn(tus_resumable))
+        .with_state(state)
+}
+
+/// Every request but `OPTIONS` must speak our version of the protocol, and
+/// every response says which version that is
+async fn tus_resumable(req: Request, next: Next) -> Response {
+    let supported = req.method() == Method::OPTIONS
+        || req
+            .headers()
+            .get(&TUS_RESUMABLE)
+            .is_some_and(|version| version == TUS_VERSION);
+
+    let mut response = if supported {
+        next.run(req).await
+    } else {
+        (
+            StatusCode::PRECONDITION_FAILED,
+            [(TUS_VERSION_HEADER, TUS_VERSION)],
+        )
+            .into_response()
+    };
+
+    response
+        .headers_mut()
+        .insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+
+    response
+}
+
+async fn describe_server() -> impl IntoResponse {
+    let max_size = config::config().await.upload_max_size();
+
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_VERSION_HEADER, TUS_VERSION.to_string()),
+            (TUS_EXTENSION, TUS_EXTENSIONS.to_string()),
+            (TUS_MAX_SIZE, max_size.to_string()),
+        ],
+    )
+}
+
+/// Start an upload. The video's `title`, `description` and `visibility` are
+/// given in the `Upload-Metadata` header.
+async fn create_upload(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    use schema::uploads::dsl::{
+        description, id, title, updated_at, upload_length, uploads, user_id, visibility,
+    };
+
+    let config = config::config().await;
+
+    let length = parse_header::<u64>(&headers, &UPLOAD_LENGTH)?.ok_or((
+        StatusCode::BAD_REQUEST,
+        "Missing Upload-Length header".to_string(),
+    ))?;
+
+    if length > config.upload_max_size() {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "The upload exceeds the maximum size".to_string(),
+        ));
+    }
+
+    let mut metadata = parse_metadata(&headers)?;
+
+    let new_title = metadata
+        .remove("title")
+        .filter(|value| !value.is_empty())
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "Missing title in Upload-Metadata".to_string(),
+        ))?;
+
+    let new_description = metadata.remove("description").unwrap_or_default();
+
+    ingest::validate_details(Some(&new_title), Some(&new_description))?;
+
+    let new_visibility = metadata
+        .remove("visibility")
+        .map(|value| value.parse::<models::Visibility>())
+        .transpose()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?
+        .unwrap_or_default();
+
+    let upload_id = uuid::Uuid::new_v4();
+
+    tokio::fs::create_dir_all(config.upload_dir())
+        .await
+        .map_err(errors::internal_error)?;
+
+    tokio::fs::File::create(upload_path(upload_id).await)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let created_at: chrono::DateTime<chrono::Utc> = diesel::insert_into(uploads)
+        .values((
+            id.eq(upload_id),
+            user_id.eq(logged_user.id),
+            title.eq(new_title),
+            description.eq(new_description),
+            upload_length.eq(length as i64),
+            visibility.eq(new_visibility),
+        ))
+        .returning(updated_at)
+        .get_result(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok((
+        StatusCode::CREATED,
+        [
+            (header::LOCATION, format!("/videos/uploads/{upload_id}")),
+            (
+                UPLOAD_EXPIRES,
+                http_date(created_at + config.upload_expiry()),
+            ),
+        ],
+    ))
+}
+
+async fn get_offset(
+    State(state): State<AppState>,
+    Path(upload_id): Path<uuid::Uuid>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<Response, (StatusCode, String)> {
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let upload = find_upload(&mut conn, upload_id, &logged_user).await?;
+
+    Ok(offset_response(StatusCode::OK, &upload).await)
+}
+
+/// Append the request body at `Upload-Offset`, and ingest the video once the
+/// upload is complete
+async fn append_chunk(
+    State(state): State<AppState>,
+    Path(upload_id): Path<uuid::Uuid>,
+    Extension(logged_user): Extension<models::User>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, (StatusCode, String)> {
+    use schema::uploads::dsl::{updated_at, upload_offset, uploads, video_id};
+
+    if headers
+        .get(header::CONTENT_TYPE)
+        .is_none_or(|content_type| content_type != OFFSET_OCTET_STREAM)
+    {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Content-Type must be {OFFSET_OCTET_STREAM}"),
+        ));
+    }
+
+    let offset = parse_header::<u64>(&headers, &UPLOAD_OFFSET)?.ok_or((
+        StatusCode::BAD_REQUEST,
+        "Missing Upload-Offset header".to_string(),
+    ))?;
+
+    let (mut file, mut upload) = {
+        let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+        // Ownership first, so that other users can't tell which ids exist
+        find_upload(&mut conn, upload_id, &logged_user).await?;
+
+        // Held until the end of the request
+        let file = lock_upload_file(upload_id).await?;
+
+        // Another request may have moved the offset before we got the lock
+        let upload = find_upload(&mut conn, upload_id, &logged_user).await?;
+
+        (file, upload)
+    };
+
+    if offset != upload.upload_offset as u64 {
+        return Err((
+            StatusCode::CONFLICT,
+            "Upload-Offset does not match the current offset".to_string(),
+        ));
+    }
+
+    let remaining = (upload.upload_length - upload.upload_offset) as u64;
+
+    // Drop anything an interrupted request wrote past the recorded offset
+    file.set_len(offset).await.map_err(errors::internal_error)?;
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(errors::internal_error)?;
+
+    let mut received = 0;
+    let mut result = Ok(());
+    let mut chunks = body.into_data_stream();
+
+    while let Some(chunk) = chunks.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                result = Err((StatusCode::BAD_REQUEST, err.to_string()));
+                break;
+            }
+        };
+
+        if received + chunk.len() as u64 > remaining {
+            result = Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "The chunk exceeds Upload-Length".to_string(),
+            ));
+            break;
+        }
+
+        if let Err(err) = file.write_all(&chunk).await {
+            result = Err(errors::internal_error(err));
+            break;
+        }
+
+        received += chunk.len() as u64;
+    }
+
+    // Whatever made it to disk counts, even if the client went away
+    file.sync_data().await.map_err(errors::internal_error)?;
+
+    upload.upload_offset += received as i64;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    // Any request pushes the expiry back, even one that wrote nothing
+    upload.updated_at = diesel::update(uploads.find(upload_id))
+        .set((
+            upload_offset.eq(upload.upload_offset),
+            updated_at.eq(diesel::dsl::now),
+        ))
+        .returning(updated_at)
+        .get_result(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    result?;
+
+    // A retry of a complete upload whose ingestion failed has an empty body
+    if upload.upload_offset == upload.upload_length && upload.video_id.is_none() {
+        drop(conn);
+
+        let new_upload = ingest::NewUpload {
+            title: upload.title.clone(),
+            description: upload.description.clone(),
+            author_id: upload.user_id,
+            visibility: upload.visibility,
+        };
+
+        let path = upload_path(upload_id).await;
+
+        let ingested = ingest::ingest_file(&state, new_upload, path.clone()).await;
+
+        let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+        match ingested {
+            Ok(video) => {
+                diesel::update(uploads.find(upload_id))
+                    .set(video_id.eq(video.id))
+                    .execute(&mut conn)
+                    .await
+                    .map_err(errors::internal_error)?;
+
+                upload.video_id = Some(video.id);
+            }
+            // The file will never be a valid video, don't keep it around
+            Err((status, message)) if status.is_client_error() => {
+                diesel::delete(uploads.find(upload_id))
+                    .execute(&mut conn)
+                    .await
+                    .map_err(errors::internal_error)?;
+
+                if let Err(err) = tokio::fs::remove_file(&path).await {
+                    tracing::warn!(%upload_id, "cannot remove rejected upload: {err}");
+                }
+
+                return Err((status, message));
+            }
+            Err(err) => return Err(err),
+        }
+
+        // The original is now in the storage
+        if let Err(err) = tokio::fs::remove_file(&path).await {
+            tracing::warn!(%upload_id, "cannot remove ingested upload: {err}");
+        }
+    }
+
+    Ok(offset_response(StatusCode::NO_CONTENT, &upload).await)
+}
+
+async fn terminate_upload(
+    State(state): State<AppState>,
+    Path(upload_id): Path<uuid::Uuid>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::uploads::dsl::uploads;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    // Ownership first, so that other users can't tell which ids exist
+    find_upload(&mut conn, upload_id, &logged_user).await?;
+
+    // Don't pull the file from under a request still writing to it
+    let _file = lock_upload_file(upload_id).await?;
+
+    let upload = find_upload(&mut conn, upload_id, &logged_user).await?;
+
+    diesel::delete(uploads.find(upload.id))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    tokio::fs::remove_file(upload_path(upload_id).await)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Uploads are only visible to the user who created them, and not at all
+/// once expired
+async fn find_upload(
+    conn: &mut AsyncPgConnection,
+    upload_id: uuid::Uuid,
+    logged_user: &models::User,
+) -> Result<models::Upload, (StatusCode, String)> {
+    use schema::uploads::dsl::{updated_at, uploads, user_id};
+
+    let expired_before = chrono::Utc::now() - config::config().await.upload_expiry();
+
+    uploads
+        .find(upload_id)
+        .filter(user_id.eq(logged_user.id))
+        .filter(updated_at.ge(expired_before))
+        .select(models::Upload::as_select())
+        .first(conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .map_not_found()
+}
+
+async fn offset_response(status: StatusCode, upload: &models::Upload) -> Response {
+    let mut response = (
+        status,
+        [
+            (UPLOAD_OFFSET, upload.upload_offset.to_string()),
+            (UPLOAD_LENGTH, upload.upload_length.to_string()),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+    )
+        .into_response();
+
+    match upload.video_id {
+        Some(ingested_video) => {
+            response
+                .headers_mut()
+                .insert(VIDEO_ID, HeaderValue::from(ingested_video));
+        }
+        None => {
+            let expires_at = upload.updated_at + config::config().await.upload_expiry();
+
+            if let Ok(expires_at) = HeaderValue::from_str(&http_date(expires_at)) {
+                response.headers_mut().insert(UPLOAD_EXPIRES, expires_at);
+            }
+        }
+    }
+
+    response
+}
+
+/// The `IMF-fixdate` format of RFC 9110, which `Upload-Expires` uses
+fn http_date(date: chrono::DateTime<chrono::Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Delete the rows and files of uploads past their expiry, finished ones
+/// included since their `Video-Id` isn't needed anymore. Returns how many
+/// were removed.
+pub async fn remove_expired(state: &AppState) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    use schema::uploads::dsl::{id, updated_at, uploads};
+
+    let expired_before = chrono::Utc::now() - config::config().await.upload_expiry();
+
+    let mut conn = state.db_pool.get().await?;
+
+    let expired = uploads
+        .select(id)
+        .filter(updated_at.lt(expired_before))
+        .load::<uuid::Uuid>(&mut conn)
+        .await?;
+
+    let mut removed = 0;
+
+    for upload_id in expired {
+        // A request that is still writing keeps the upload for another sweep
+        let file = match lock_upload_file(upload_id).await {
+            Ok(file) => Some(file),
+            Err((StatusCode::NOT_FOUND, _)) => None,
+            Err((_, message)) => {
+                tracing::warn!(%upload_id, "cannot lock expired upload: {message}");
+                continue;
+            }
+        };
+
+        // A request may have refreshed the upload since it was listed
+        let deleted = match diesel::delete(uploads.find(upload_id))
+            .filter(updated_at.lt(expired_before))
+            .execute(&mut conn)
+            .await
+        {
+            Ok(deleted) => deleted,
+            Err(err) => {
+                tracing::warn!(%upload_id, "cannot delete expired upload: {err}");
+                continue;
+            }
+        };
+
+        if deleted == 0 {
+            continue;
+        }
+
+        if file.is_some() {
+            if let Err(err) = tokio::fs::remove_file(upload_path(upload_id).await).await {
+                tracing::warn!(%upload_id, "cannot remove expired upload: {err}");
+            }
+        }
+
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+async fn upload_path(upload_id: uuid::Uuid) -> PathBuf {
+    config::config()
+        .await
+        .upload_dir()
+        .join(upload_id.to_string())
+}
+
+/// Open the data file of an upload, making sure no other request is writing
+/// to it at the same time
+async fn lock_upload_file(upload_id: uuid::Uuid) -> Result<tokio::fs::File, (StatusCode, String)> {
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(upload_path(upload_id).await)
+        .await
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => (StatusCode::NOT_FOUND, "Not Found".to_string()),
+            _ => errors::internal_error(err),
+        })?
+        .into_std()
+        .await;
+
+    match file.try_lock() {
+        Ok(()) => Ok(tokio::fs::File::from_std(file)),
+        Err(std::fs::TryLockError::WouldBlock) => Err((
+            StatusCode::LOCKED,
+            "The upload is already being written to".to_string(),
+        )),
+        Err(std::fs::TryLockError::Error(err)) => Err(errors::internal_error(err)),
+    }
+}
+
+fn parse_header<T: FromStr>(
+    headers: &HeaderMap,
+    name: &HeaderName,
+) -> Result<Option<T>, (StatusCode, String)> {
+    headers
+        .get(name)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Invalid {name} header")))
+        })
+        .transpose()
+}
+
+/// `Upload-Metadata` is a list of `key base64(value)` pairs separated by commas
+fn parse_metadata(headers: &HeaderMap) -> Result<HashMap<String, String>, (StatusCode, String)> {
+    let Some(value) = headers.get(&UPLOAD_METADATA) else {
+        return Ok(HashMap::new());
+    };
+
+    let invalid = || {
+        (
+            StatusCode::BAD_REQUEST,
+            "Invalid Upload-Metadata header".to_string(),
+        )
+    };
+
+    value
+        .to_str()
+        .map_err(|_| invalid())?
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, encoded) = pair.split_once(' ').unwrap_or((pair, ""));
+
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(encoded.trim())
+                .map_err(|_| invalid())?;
+
+            Ok((
+                key.to_owned(),
+                String::from_utf8(decoded).map_err(|_| invalid())?,
+            ))
+        })
+        .collect()
+}
@@ -0,0 +1,286 @@
+//! Channels, i.e. users as seen by everyone else
+
+use crate::{auth, errors, models, pagination, schema, AppState};
+
+use super::videos;
+
+use errors::NotFoundExt;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+
+use diesel::prelude::*;
+use diesel::sql_types::BigInt;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use serde::Deserialize;
+
+pub fn router<S>(state: AppState) -> Router<S> {
+    Router::new()
+        .route("/:id", get(get_channel))
+        .route("/by-name/:username", get(get_channel_by_name))
+        .route(
+            "/:id/subscribe",
+            post(subscribe)
+                .delete(unsubscribe)
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    auth::middleware,
+                )),
+        )
+        .with_state(state)
+}
+
+async fn subscribe(
+    State(state): State<AppState>,
+    Path(target_user_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::subscriptions::dsl::{channel_id, subscriber_id, subscriptions};
+    use schema::users::dsl::users;
+
+    if target_user_id == logged_user.id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Cannot subscribe to yourself".to_string(),
+        ));
+    }
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let channel = users
+        .select(models::User::as_select())
+        .find(target_user_id)
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .map_not_found()?;
+
+    // Subscribing twice is fine
+    diesel::insert_into(subscriptions)
+        .values((subscriber_id.eq(logged_user.id), channel_id.eq(channel.id)))
+        .on_conflict_do_nothing()
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn unsubscribe(
+    State(state): State<AppState>,
+    Path(target_user_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::subscriptions::dsl::{channel_id, subscriber_id, subscriptions};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    diesel::delete(subscriptions)
+        .filter(subscriber_id.eq(logged_user.id))
+        .filter(channel_id.eq(target_user_id))
+        .execute(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ChannelSort {
+    #[default]
+    Newest,
+    /// Most viewed first
+    Popular,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChannelQuery {
+    #[serde(default)]
+    sort: ChannelSort,
+}
+
+async fn get_channel(
+    State(state): State<AppState>,
+    Path(target_user_id): Path<i32>,
+    auth::OptionalUser(logged_user): auth::OptionalUser,
+    Query(params): Query<ChannelQuery>,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<Json<models::Channel>, (StatusCode, String)> {
+    use schema::users::dsl::users;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let user = users
+        .select(models::User::as_select())
+        .find(target_user_id)
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .map_not_found()?;
+
+    let channel = channel(&mut conn, user, logged_user.as_ref(), params.sort, &page).await?;
+
+    Ok(Json(channel))
+}
+
+async fn get_channel_by_name(
+    State(state): State<AppState>,
+    Path(target_username): Path<String>,
+    auth::OptionalUser(logged_user): auth::OptionalUser,
+    Query(params): Query<ChannelQuery>,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<Json<models::Channel>, (StatusCode, String)> {
+    use schema::users::dsl::{username, users};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let user = users
+        .select(models::User::as_select())
+        .filter(username.eq(target_username))
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .map_not_found()?;
+
+    let channel = channel(&mut conn, user, logged_user.as_ref(), params.sort, &page).await?;
+
+    Ok(Json(channel))
+}
+
+/// The channel of `user` with a page of its public videos, whoever is asking
+async fn channel(
+    conn: &mut AsyncPgConnection,
+    user: models::User,
+    logged_user: Option<&models::User>,
+    sort: ChannelSort,
+    page: &pagination::PageQuery,
+) -> Result<models::Channel, (StatusCode, String)> {
+    use schema::video_stats;
+    use schema::videos::dsl::{deleted_at, id, published_at, status, visibility};
+
+    let limit = page.limit();
+
+    let mut query = models::Video::belonging_to(&user)
+        .inner_join(schema::users::table)
+        .left_join(video_stats::table)
+        .select((
+            models::VIDEO_ALL_COLUMNS,
+            models::User::as_select(),
+            Option::<models::VideoStats>::as_select(),
+        ))
+        .filter(status.eq(models::VideoStatus::Ready))
+        .filter(deleted_at.is_null())
+        .filter(visibility.eq(models::Visibility::Public))
+        .limit(limit + 1)
+        .into_boxed();
+
+    query = match sort {
+        ChannelSort::Newest => {
+            if let Some((last_published_at, last_id)) =
+                page.cursor::<(chrono::DateTime<chrono::Utc>, i32)>()?
+            {
+                query = query.filter(
+                    published_at
+                        .lt(last_published_at)
+                        .or(published_at.eq(last_published_at).and(id.lt(last_id))),
+                );
+            }
+
+            query.order((published_at.desc(), id.desc()))
+        }
+        ChannelSort::Popular => {
+            if let Some((last_view_count, last_id)) = page.cursor::<(i64, i32)>()? {
+                query = query.filter(videos::after_count(
+                    videos::VIEW_COUNT_SQL,
+                    last_view_count,
+                    last_id,
+                ));
+            }
+
+            query
+                .order(diesel::dsl::sql::<BigInt>(videos::VIEW_COUNT_SQL).desc())
+                .then_order_by(id.desc())
+        }
+    };
+
+    let rows = query
+        .load::<(models::Video, models::User, Option<models::VideoStats>)>(conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let videos_page = match sort {
+        ChannelSort::Newest => {
+            pagination::Page::from_rows(rows, limit, |(video, _, _)| (video.published_at, video.id))
+        }
+        ChannelSort::Popular => pagination::Page::from_rows(rows, limit, |(video, _, stats)| {
+            (stats.as_ref().map_or(0, |stats| stats.view_count), video.id)
+        }),
+    };
+
+    let items = videos::videos_with_author(conn, videos_page.items, logged_user)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let total_views = total_views(conn, user.id).await?;
+
+    let is_subscribed = match logged_user {
+        Some(logged_user) => Some(is_subscribed(conn, logged_user.id, user.id).await?),
+        None => None,
+    };
+
+    Ok(models::Channel {
+        user,
+        is_subscribed,
+        total_views,
+        videos: pagination::Page {
+            items,
+            next_cursor: videos_page.next_cursor,
+        },
+    })
+}
+
+/// Views of the public videos of `channel`
+async fn total_views(
+    conn: &mut AsyncPgConnection,
+    channel: i32,
+) -> Result<i64, (StatusCode, String)> {
+    use schema::video_stats::dsl::video_stats;
+    use schema::videos::dsl::{author_id, deleted_at, visibility};
+
+    // `sum` of a bigint is a numeric
+    schema::videos::table
+        .inner_join(video_stats)
+        .select(diesel::dsl::sql::<BigInt>(
+            "coalesce(sum(video_stats.view_count), 0)::bigint",
+        ))
+        .filter(author_id.eq(channel))
+        .filter(deleted_at.is_null())
+        .filter(visibility.eq(models::Visibility::Public))
+        .get_result(conn)
+        .await
+        .map_err(errors::internal_error)
+}
+
+async fn is_subscribed(
+    conn: &mut AsyncPgConnection,
+    subscriber: i32,
+    channel: i32,
+) -> Result<bool, (StatusCode, String)> {
+    use schema::subscriptions::dsl::{channel_id, subscriber_id, subscriptions};
+
+    diesel::select(diesel::dsl::exists(
+        subscriptions
+            .filter(subscriber_id.eq(subscriber))
+            .filter(channel_id.eq(channel)),
+    ))
+    .get_result(conn)
+    .await
+    .map_err(errors::internal_error)
+}
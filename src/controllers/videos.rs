@@ -1,10 +1,17 @@
-use crate::{auth, errors, models, schema, video_util, AppState};
+use crate::{
+    auth, config, errors, ingest, jobs, models, pagination, playback, schema, streaming, thumbnail,
+    trash, video_util, views, AppState,
+};
 
 use errors::NotFoundExt;
 
-use axum::extract::{DefaultBodyLimit, Path};
-use axum::http::StatusCode;
-use axum::routing::{get, post};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Path};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, patch, post, put};
 use axum::{extract::State, Json};
 use axum::{Extension, Router};
 
@@ -14,7 +21,7 @@ use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
 use diesel_full_text_search::*;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tempfile::NamedTempFile;
 
 pub fn router<S>(state: AppState) -> Router<S> {
@@ -28,6 +35,58 @@ pub fn router<S>(state: AppState) -> Router<S> {
             )),
         )
         .route("/:id", get(get_video))
+        .route(
+            "/:id",
+            patch(update_video).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/:id",
+            delete(delete_video).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/:id/restore",
+            post(restore_video).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/trash",
+            get(list_trash).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route("/:id/stream", get(stream_video))
+        .route("/:id/hls/*file", get(stream_hls))
+        .route(
+            "/:id/playback",
+            get(get_playback_url).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/:id/retry",
+            post(retry_processing).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route(
+            "/:id/thumbnail",
+            put(upload_thumbnail).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::middleware,
+            )),
+        )
+        .route("/:id/thumbnails/:thumbnail/:size", get(get_thumbnail))
         .route(
             "/:id/like",
             post(like_video).route_layer(axum::middleware::from_fn_with_state(
@@ -35,59 +94,400 @@ pub fn router<S>(state: AppState) -> Router<S> {
                 auth::middleware,
             )),
         )
+        .route("/:id/views", post(record_view))
+        .route(
+            "/:id/progress",
+            post(super::history::record_progress).route_layer(
+                axum::middleware::from_fn_with_state(state.clone(), auth::middleware),
+            ),
+        )
         .layer(DefaultBodyLimit::disable())
         .with_state(state)
 }
 
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum VideoSort {
+    Newest,
+    Oldest,
+    MostLiked,
+    MostViewed,
+    /// Best match of the search first, only when searching
+    Relevance,
+}
+
+/// Buckets of `duration_seconds`
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DurationFilter {
+    /// Under 4 minutes
+    Short,
+    /// From 4 to 20 minutes
+    Medium,
+    /// Over 20 minutes
+    Long,
+}
+
+impl DurationFilter {
+    /// Inclusive lower and exclusive upper bounds, in seconds
+    fn bounds(self) -> (i64, Option<i64>) {
+        match self {
+            DurationFilter::Short => (0, Some(4 * 60)),
+            DurationFilter::Medium => (4 * 60, Some(20 * 60)),
+            DurationFilter::Long => (20 * 60, None),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct ListVideoQuery {
     search: Option<String>,
+
+    /// Relevance when searching, newest otherwise
+    sort: Option<VideoSort>,
+
+    duration: Option<DurationFilter>,
+    published_after: Option<chrono::DateTime<chrono::Utc>>,
+    published_before: Option<chrono::DateTime<chrono::Utc>>,
+    author_id: Option<i32>,
+
+    /// Username of the author
+    author: Option<String>,
+
+    /// Smallest accepted height, or width of portrait videos, e.g. 720
+    min_resolution: Option<i32>,
+
+    /// Whether the original has a subtitle stream
+    has_captions: Option<bool>,
+
+    /// Inserted before each match in the highlights of a search
+    highlight_start: Option<String>,
+    /// Inserted after each match in the highlights of a search
+    highlight_stop: Option<String>,
+}
+
+/// Control characters rather than HTML, since titles and descriptions are not
+/// escaped. Clients split on them and render the parts as text.
+const DEFAULT_HIGHLIGHT_START: &str = "\u{2}";
+const DEFAULT_HIGHLIGHT_STOP: &str = "\u{3}";
+const MAX_HIGHLIGHT_MARKER_LENGTH: usize = 20;
+
+diesel::sql_function! {
+    #[sql_name = "ts_headline"]
+    fn ts_headline_with_options(
+        config: RegConfig,
+        document: diesel::sql_types::Text,
+        query: TsQuery,
+        options: diesel::sql_types::Text,
+    ) -> diesel::sql_types::Text;
+}
+
+/// Options of `ts_headline` putting `start` and `stop` around matches, values
+/// are double quoted so that markers may contain spaces or commas
+fn headline_options(start: &str, stop: &str) -> Result<String, (StatusCode, String)> {
+    for marker in [start, stop] {
+        if marker.is_empty()
+            || marker.chars().count() > MAX_HIGHLIGHT_MARKER_LENGTH
+            || marker.contains(['"', '\\'])
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Highlight markers must be 1 to {MAX_HIGHLIGHT_MARKER_LENGTH} characters, \
+                     without quotes or backslashes"
+                ),
+            ));
+        }
+    }
+
+    Ok(format!(r#"StartSel="{start}", StopSel="{stop}""#))
 }
 
 async fn list_videos(
     State(state): State<AppState>,
+    auth::OptionalUser(logged_user): auth::OptionalUser,
     axum::extract::Query(params): axum::extract::Query<ListVideoQuery>,
-) -> Result<Json<Vec<models::VideoWithAuthor>>, (StatusCode, String)> {
+    axum::extract::Query(page): axum::extract::Query<pagination::PageQuery>,
+) -> Result<Json<pagination::Page<models::VideoWithAuthor>>, (StatusCode, String)> {
     use schema::users::dsl::users;
+    use schema::video_stats;
     use schema::videos::dsl::textsearchable_index_col;
-    use schema::videos::dsl::videos;
+    use schema::videos::dsl::{
+        author_id, duration_seconds, id, published_at, status, videos, visibility,
+    };
 
-    let selection = (models::VIDEO_ALL_COLUMNS, models::User::as_select());
+    let selection = (
+        models::VIDEO_ALL_COLUMNS,
+        models::User::as_select(),
+        Option::<models::VideoStats>::as_select(),
+    );
 
-    let mut query = videos.inner_join(users).select(selection).into_boxed();
+    let limit = page.limit();
 
-    if let Some(search_term) = params.search {
-        if !search_term.is_empty() {
-            let q = diesel::dsl::sql::<TsQuery>("plainto_tsquery('english', ")
+    let mut query = videos
+        .inner_join(users)
+        .left_join(video_stats::table)
+        .select(selection)
+        .filter(status.eq(models::VideoStatus::Ready))
+        .filter(schema::videos::deleted_at.is_null())
+        .filter(visibility.eq(models::Visibility::Public))
+        .limit(limit + 1)
+        .into_boxed();
+
+    let search_query = params
+        .search
+        .filter(|search_term| !search_term.is_empty())
+        .map(|search_term| {
+            diesel::dsl::sql::<TsQuery>("plainto_tsquery('english', ")
                 .bind::<diesel::sql_types::Text, _>(search_term)
-                .sql(")");
+                .sql(")")
+        });
+
+    if let Some(q) = &search_query {
+        query = query.filter(q.clone().matches(textsearchable_index_col));
+    }
 
-            query = query.filter(q.clone().matches(textsearchable_index_col));
+    // Markers only matter, and are only checked, when searching
+    let highlight_options = match &search_query {
+        Some(_) => {
+            let description_options = headline_options(
+                params
+                    .highlight_start
+                    .as_deref()
+                    .unwrap_or(DEFAULT_HIGHLIGHT_START),
+                params
+                    .highlight_stop
+                    .as_deref()
+                    .unwrap_or(DEFAULT_HIGHLIGHT_STOP),
+            )?;
 
-            let rank = ts_rank_cd(textsearchable_index_col, q);
-            query = query.then_order_by(rank.desc())
+            // The whole title, but only the best fragment of the description
+            let title_options = format!("{description_options}, HighlightAll=true");
+
+            Some((title_options, description_options))
+        }
+        None => None,
+    };
+
+    if let Some(duration) = params.duration {
+        let (min_duration, max_duration) = duration.bounds();
+
+        query = query.filter(duration_seconds.ge(min_duration));
+
+        if let Some(max_duration) = max_duration {
+            query = query.filter(duration_seconds.lt(max_duration));
         }
     }
 
+    if let Some(published_after) = params.published_after {
+        query = query.filter(published_at.ge(published_after));
+    }
+
+    if let Some(published_before) = params.published_before {
+        query = query.filter(published_at.lt(published_before));
+    }
+
+    if let Some(target_author_id) = params.author_id {
+        query = query.filter(author_id.eq(target_author_id));
+    }
+
+    if let Some(author_username) = params.author {
+        query = query.filter(schema::users::username.eq(author_username));
+    }
+
+    if let Some(min_resolution) = params.min_resolution {
+        use schema::video_metadata::dsl::{video_id, video_metadata};
+
+        // The shorter side, so that portrait videos compare like landscape ones
+        let resolution = diesel::dsl::sql::<diesel::sql_types::Integer>(
+            "least(video_metadata.width, video_metadata.height)",
+        );
+
+        query = query.filter(
+            id.eq_any(
+                video_metadata
+                    .select(video_id)
+                    .filter(resolution.ge(min_resolution)),
+            ),
+        );
+    }
+
+    if let Some(with_captions) = params.has_captions {
+        use schema::video_metadata::dsl::{has_captions, video_id, video_metadata};
+
+        query = query.filter(
+            id.eq_any(
+                video_metadata
+                    .select(video_id)
+                    .filter(has_captions.eq(with_captions)),
+            ),
+        );
+    }
+
+    let sort = params.sort.unwrap_or(match search_query {
+        Some(_) => VideoSort::Relevance,
+        None => VideoSort::Newest,
+    });
+
+    query = match sort {
+        VideoSort::Newest => {
+            if let Some((last_published_at, last_id)) =
+                page.cursor::<(chrono::DateTime<chrono::Utc>, i32)>()?
+            {
+                query = query.filter(
+                    published_at
+                        .lt(last_published_at)
+                        .or(published_at.eq(last_published_at).and(id.lt(last_id))),
+                );
+            }
+
+            query.order((published_at.desc(), id.desc()))
+        }
+        VideoSort::Oldest => {
+            if let Some((last_published_at, last_id)) =
+                page.cursor::<(chrono::DateTime<chrono::Utc>, i32)>()?
+            {
+                query = query.filter(
+                    published_at
+                        .gt(last_published_at)
+                        .or(published_at.eq(last_published_at).and(id.gt(last_id))),
+                );
+            }
+
+            query.order((published_at.asc(), id.asc()))
+        }
+        VideoSort::MostLiked | VideoSort::MostViewed => {
+            let count_sql = match sort {
+                VideoSort::MostLiked => LIKE_COUNT_SQL,
+                _ => VIEW_COUNT_SQL,
+            };
+
+            if let Some((last_count, last_id)) = page.cursor::<(i64, i32)>()? {
+                query = query.filter(after_count(count_sql, last_count, last_id));
+            }
+
+            query
+                .order(diesel::dsl::sql::<diesel::sql_types::BigInt>(count_sql).desc())
+                .then_order_by(id.desc())
+        }
+        VideoSort::Relevance => {
+            let Some(q) = search_query.clone() else {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "Sorting by relevance needs a search".to_string(),
+                ));
+            };
+
+            let rank = ts_rank_cd(textsearchable_index_col, q);
+
+            if let Some((last_rank, last_id)) = page.cursor::<(f32, i32)>()? {
+                query = query.filter(
+                    rank.clone()
+                        .lt(last_rank)
+                        .or(rank.clone().eq(last_rank).and(id.lt(last_id))),
+                );
+            }
+
+            query.order((rank.desc(), id.desc()))
+        }
+    };
+
     let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
 
-    let res = query
-        .load::<(models::Video, models::User)>(&mut conn)
+    let rows = query
+        .load::<(models::Video, models::User, Option<models::VideoStats>)>(&mut conn)
         .await
         .map_err(errors::internal_error)?;
 
-    let videos_with_author = res
-        .into_iter()
-        .map(|(video, author)| models::VideoWithAuthor { video, author })
-        .collect::<Vec<_>>();
+    let videos_page = match sort {
+        VideoSort::Newest | VideoSort::Oldest => {
+            pagination::Page::from_rows(rows, limit, |(video, _, _)| (video.published_at, video.id))
+        }
+        VideoSort::MostLiked => pagination::Page::from_rows(rows, limit, |(video, _, stats)| {
+            (stats.as_ref().map_or(0, |stats| stats.like_count), video.id)
+        }),
+        VideoSort::MostViewed => pagination::Page::from_rows(rows, limit, |(video, _, stats)| {
+            (stats.as_ref().map_or(0, |stats| stats.view_count), video.id)
+        }),
+        VideoSort::Relevance => {
+            // The rank isn't selected, only the last video of a page that has
+            // a next one needs it
+            let last_rank = match (rows.get(limit as usize), &search_query) {
+                (Some(_), Some(q)) => {
+                    let (last_video, _, _) = &rows[limit as usize - 1];
+
+                    videos
+                        .select(ts_rank_cd(textsearchable_index_col, q.clone()))
+                        .find(last_video.id)
+                        .first::<f32>(&mut conn)
+                        .await
+                        .map_err(errors::internal_error)?
+                }
+                _ => 0.0,
+            };
+
+            pagination::Page::from_rows(rows, limit, |(video, _, _)| (last_rank, video.id))
+        }
+    };
+
+    let mut items = videos_with_author(&mut conn, videos_page.items, logged_user.as_ref())
+        .await
+        .map_err(errors::internal_error)?;
+
+    if let (Some(q), Some((title_options, description_options))) = (search_query, highlight_options)
+    {
+        use schema::videos::dsl::{description, title};
+
+        let video_ids = items.iter().map(|item| item.video.id).collect::<Vec<_>>();
+
+        let mut highlights = videos
+            .select((
+                id,
+                ts_headline_with_options(
+                    configuration::TsConfigurationByName("english"),
+                    title,
+                    q.clone(),
+                    title_options,
+                ),
+                ts_headline_with_options(
+                    configuration::TsConfigurationByName("english"),
+                    description,
+                    q,
+                    description_options,
+                ),
+            ))
+            .filter(id.eq_any(video_ids))
+            .load::<(i32, String, String)>(&mut conn)
+            .await
+            .map_err(errors::internal_error)?
+            .into_iter()
+            .map(|(video_id, title_highlight, description_highlight)| {
+                (
+                    video_id,
+                    models::SearchHighlight {
+                        title: title_highlight,
+                        description: description_highlight,
+                    },
+                )
+            })
+            .collect::<HashMap<_, _>>();
+
+        for item in &mut items {
+            item.highlight = highlights.remove(&item.video.id);
+        }
+    }
 
-    Ok(Json(videos_with_author))
+    Ok(Json(pagination::Page {
+        items,
+        next_cursor: videos_page.next_cursor,
+    }))
 }
 
 #[derive(TryFromMultipart)]
 struct UploadVideoRequest {
     title: String,
     description: String,
+    visibility: Option<String>,
     #[form_data(limit = "unlimited")]
     video: FieldData<NamedTempFile>,
 }
@@ -97,60 +497,641 @@ async fn upload(
     Extension(logged_user): Extension<models::User>,
     TypedMultipart(upload_request): TypedMultipart<UploadVideoRequest>,
 ) -> Result<Json<models::Video>, (StatusCode, String)> {
+    ingest::validate_details(
+        Some(&upload_request.title),
+        Some(&upload_request.description),
+    )?;
+
+    let visibility = upload_request
+        .visibility
+        .map(|value| value.parse::<models::Visibility>())
+        .transpose()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?
+        .unwrap_or_default();
+
+    let new_upload = ingest::NewUpload {
+        title: upload_request.title,
+        description: upload_request.description,
+        author_id: logged_user.id,
+        visibility,
+    };
+
+    // The temporary file lives until the end of the request
+    let video_path = upload_request.video.contents.path().to_owned();
+
+    let inserted_video = ingest::ingest_file(&state, new_upload, video_path).await?;
+
+    Ok(Json(inserted_video))
+}
+
+async fn get_video(
+    State(state): State<AppState>,
+    Path(video_id): Path<i32>,
+    auth::OptionalUser(logged_user): auth::OptionalUser,
+) -> Result<Json<models::VideoDetails>, (StatusCode, String)> {
     use schema::videos::dsl::videos;
+    use schema::{video_metadata, video_stats};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let (target_video, metadata, stats) = videos
+        .left_join(video_metadata::table)
+        .left_join(video_stats::table)
+        .select((
+            models::Video::as_select(),
+            Option::<models::MediaMetadata>::as_select(),
+            Option::<models::VideoStats>::as_select(),
+        ))
+        .filter(schema::videos::id.eq(video_id))
+        .filter(schema::videos::deleted_at.is_null())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|(video, _, _): &(models::Video, _, _)| video.is_visible_to(logged_user.as_ref()))
+        .map_not_found()?;
+
+    let mut ratings = ratings(&mut conn, logged_user.as_ref(), &[target_video.id])
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(Json(models::VideoDetails {
+        is_liking: ratings.remove(&target_video.id),
+        hls_url: target_video.hls_url(),
+        stats: stats.unwrap_or_default(),
+        video: target_video,
+        metadata,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateVideoBody {
+    title: Option<String>,
+    description: Option<String>,
+    visibility: Option<models::Visibility>,
+    comments_enabled: Option<bool>,
+}
+
+impl UpdateVideoBody {
+    /// Every invalid field with the reason, so a form can show them all at once
+    fn validate(&self) -> Result<(), (StatusCode, String)> {
+        ingest::validate_details(self.title.as_deref(), self.description.as_deref())
+    }
+}
+
+/// Let the author change what they told us about the video when uploading it
+async fn update_video(
+    State(state): State<AppState>,
+    Path(video_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+    Json(body): Json<UpdateVideoBody>,
+) -> Result<Json<models::Video>, (StatusCode, String)> {
+    use schema::videos::dsl::videos;
+
+    body.validate()?;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = videos
+        .select(models::Video::as_select())
+        .find(video_id)
+        .filter(schema::videos::deleted_at.is_null())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|video| video.is_visible_to(Some(&logged_user)))
+        .map_not_found()?;
+
+    if target_video.author_id != logged_user.id {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    let changes = models::VideoChanges {
+        title: body.title.map(|title| title.trim().to_string()),
+        description: body.description,
+        visibility: body.visibility,
+        comments_enabled: body.comments_enabled,
+    };
 
-    let bucket_id = uuid::Uuid::new_v4();
+    // Diesel refuses to run an update without anything to set
+    if changes.title.is_none()
+        && changes.description.is_none()
+        && changes.visibility.is_none()
+        && changes.comments_enabled.is_none()
+    {
+        return Ok(Json(target_video));
+    }
 
-    // FIXME: don't return internal server error if the uploaded file is not a video
-    let video_duration = video_util::get_video_duration(upload_request.video.contents.path())
+    let updated_video = diesel::update(videos.find(video_id))
+        .set(&changes)
+        .returning(models::VIDEO_ALL_COLUMNS)
+        .get_result::<models::Video>(&mut conn)
+        .await
         .map_err(errors::internal_error)?;
 
-    let mut tmp_video_file = tokio::fs::File::from_std(upload_request.video.contents.into_file());
+    Ok(Json(updated_video))
+}
+
+/// Move a video to the trash, it stays restorable until the sweeper purges it
+async fn delete_video(
+    State(state): State<AppState>,
+    Path(video_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::videos::dsl::{deleted_at, videos};
 
-    state
-        .s3
-        .put_object_stream(&mut tmp_video_file, bucket_id.to_string())
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = videos
+        .select(models::Video::as_select())
+        .find(video_id)
+        .filter(deleted_at.is_null())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|video| video.is_visible_to(Some(&logged_user)))
+        .map_not_found()?;
+
+    if target_video.author_id != logged_user.id {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    diesel::update(videos.find(video_id))
+        .set(deleted_at.eq(diesel::dsl::now))
+        .execute(&mut conn)
         .await
         .map_err(errors::internal_error)?;
 
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Take a video of the logged user back out of the trash
+async fn restore_video(
+    State(state): State<AppState>,
+    Path(video_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<Json<models::Video>, (StatusCode, String)> {
+    use schema::videos::dsl::{author_id, deleted_at, videos};
+
     let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
 
-    let new_video = models::NewVideo {
-        title: upload_request.title,
-        description: upload_request.description,
-        duration_seconds: video_duration.num_seconds(),
-        bucket: bucket_id,
-        author_id: logged_user.id,
+    let target_video = videos
+        .select(models::Video::as_select())
+        .find(video_id)
+        .filter(author_id.eq(logged_user.id))
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .map_not_found()?;
+
+    let Some(trashed_at) = target_video.deleted_at else {
+        return Err((StatusCode::CONFLICT, "Video is not deleted".to_string()));
     };
 
-    let inserted_video = diesel::insert_into(videos)
-        .values(&new_video)
+    if !trash::is_restorable(trashed_at).await {
+        return Err((
+            StatusCode::GONE,
+            "Video has been in the trash for too long".to_string(),
+        ));
+    }
+
+    let restored_video = diesel::update(videos.find(video_id))
+        .set(deleted_at.eq(None::<chrono::DateTime<chrono::Utc>>))
         .returning(models::VIDEO_ALL_COLUMNS)
-        .get_result(&mut conn)
+        .get_result::<models::Video>(&mut conn)
         .await
         .map_err(errors::internal_error)?;
 
-    Ok(Json(inserted_video))
+    Ok(Json(restored_video))
 }
 
-async fn get_video(
+/// Deleted videos of the logged user, most recently deleted first
+async fn list_trash(
+    State(state): State<AppState>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<Json<Vec<models::Video>>, (StatusCode, String)> {
+    use schema::videos::dsl::{author_id, deleted_at, videos};
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let trashed_videos = videos
+        .select(models::VIDEO_ALL_COLUMNS)
+        .filter(author_id.eq(logged_user.id))
+        .filter(deleted_at.is_not_null())
+        .order(deleted_at.desc())
+        .load::<models::Video>(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    Ok(Json(trashed_videos))
+}
+
+/// Serve the original upload, honoring `Range` so that players can seek. A
+/// valid signed URL stands in for the credentials of the user it was issued to.
+async fn stream_video(
     State(state): State<AppState>,
     Path(video_id): Path<i32>,
-) -> Result<Json<models::Video>, (StatusCode, String)> {
+    signature: Option<axum::extract::Query<playback::StreamSignature>>,
+    auth::OptionalUser(logged_user): auth::OptionalUser,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    use schema::video_metadata;
     use schema::videos::dsl::videos;
 
+    let signature = verify_signature(video_id, playback::Resource::Stream, signature).await?;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let (target_video, metadata) = videos
+        .left_join(video_metadata::table)
+        .select((
+            models::Video::as_select(),
+            Option::<models::MediaMetadata>::as_select(),
+        ))
+        .filter(schema::videos::id.eq(video_id))
+        .filter(schema::videos::deleted_at.is_null())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|(video, _): &(models::Video, _)| {
+            may_stream(video, signature.as_ref(), logged_user.as_ref())
+        })
+        .map_not_found()?;
+
+    drop(conn);
+
+    let content_type = metadata.map_or("application/octet-stream", |metadata| {
+        video_util::container_mime_type(&metadata.container)
+    });
+
+    streaming::stream_object(
+        &state.storage,
+        &target_video.bucket.to_string(),
+        &headers,
+        content_type,
+    )
+    .await
+}
+
+/// Serve the HLS playlists and segments of a video, with the same access
+/// rules as `stream_video`. Playlists fetched through a signed URL get the
+/// signature appended to the URLs they list, so that players can follow them.
+async fn stream_hls(
+    State(state): State<AppState>,
+    Path((video_id, file)): Path<(i32, String)>,
+    signature: Option<axum::extract::Query<playback::StreamSignature>>,
+    auth::OptionalUser(logged_user): auth::OptionalUser,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    use schema::videos::dsl::videos;
+
+    // Only what the transcoder writes, never `..` or the like
+    let is_playlist = match file.rsplit_once('.') {
+        Some((_, "m3u8")) => true,
+        Some((_, "ts")) => false,
+        _ => return Err((StatusCode::NOT_FOUND, "Not Found".to_string())),
+    };
+
+    let is_safe_component = |component: &str| {
+        !component.is_empty()
+            && !component.starts_with('.')
+            && component
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    };
+
+    if !file.split('/').all(is_safe_component) {
+        return Err((StatusCode::NOT_FOUND, "Not Found".to_string()));
+    }
+
+    let signature = verify_signature(video_id, playback::Resource::Hls, signature).await?;
+
     let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
 
     let target_video = videos
         .select(models::Video::as_select())
         .find(video_id)
+        .filter(schema::videos::deleted_at.is_null())
+        .filter(schema::videos::master_playlist.is_not_null())
         .first(&mut conn)
         .await
         .optional()
         .map_err(errors::internal_error)?
+        .filter(|video: &models::Video| may_stream(video, signature.as_ref(), logged_user.as_ref()))
         .map_not_found()?;
 
-    Ok(Json(target_video))
+    drop(conn);
+
+    let key = format!("{}/hls/{file}", target_video.bucket);
+
+    if !is_playlist {
+        return streaming::stream_object(&state.storage, &key, &headers, "video/mp2t").await;
+    }
+
+    let playlist = state
+        .storage
+        .get(&key)
+        .await
+        .map_err(|err| (err.status_code(), err.to_string()))?;
+
+    let playlist = String::from_utf8(playlist).map_err(errors::internal_error)?;
+
+    let playlist = match &signature {
+        Some(signature) => sign_playlist(&playlist, &signature.query()),
+        None => playlist,
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.apple.mpegurl"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        playlist,
+    )
+        .into_response())
+}
+
+/// The signature of a playback URL for `resource`, checked, or `None` when
+/// there is none
+async fn verify_signature(
+    video_id: i32,
+    resource: playback::Resource,
+    signature: Option<axum::extract::Query<playback::StreamSignature>>,
+) -> Result<Option<playback::StreamSignature>, (StatusCode, String)> {
+    let Some(axum::extract::Query(signature)) = signature else {
+        return Ok(None);
+    };
+
+    if !playback::verify(video_id, resource, &signature).await {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Invalid or expired playback URL".to_string(),
+        ));
+    }
+
+    Ok(Some(signature))
+}
+
+/// Whether a streaming request may access `video`, acting as the user its
+/// signed URL was issued to if any, or else as the logged user
+fn may_stream(
+    video: &models::Video,
+    signature: Option<&playback::StreamSignature>,
+    logged_user: Option<&models::User>,
+) -> bool {
+    match signature {
+        Some(signature) => video
+            .visibility
+            .allows_user_id(video.author_id, Some(signature.user)),
+        None => video.is_visible_to(logged_user),
+    }
+}
+
+/// `playlist` with `query` appended to the URI of every sub-playlist and segment
+fn sign_playlist(playlist: &str, query: &str) -> String {
+    playlist
+        .lines()
+        .map(|line| {
+            if line.is_empty() || line.starts_with('#') {
+                line.to_owned()
+            } else if line.contains('?') {
+                format!("{line}&{query}")
+            } else {
+                format!("{line}?{query}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        + "\n"
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PlaybackKind {
+    /// Signed URL of the `stream` endpoint
+    #[default]
+    Api,
+    /// Presigned URL of the original in the object store
+    S3,
+    /// Signed URL of the HLS master playlist, see `stream_hls`
+    Hls,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaybackQuery {
+    #[serde(default)]
+    kind: PlaybackKind,
+}
+
+#[derive(Debug, Serialize)]
+struct PlaybackUrl {
+    url: String,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Hand out a time-limited URL the browser can play the video from
+async fn get_playback_url(
+    State(state): State<AppState>,
+    Path(video_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+    axum::extract::Query(params): axum::extract::Query<PlaybackQuery>,
+) -> Result<Json<PlaybackUrl>, (StatusCode, String)> {
+    use schema::videos::dsl::videos;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = videos
+        .select(models::Video::as_select())
+        .find(video_id)
+        .filter(schema::videos::deleted_at.is_null())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|video: &models::Video| video.is_visible_to(Some(&logged_user)))
+        .map_not_found()?;
+
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(playback::URL_TTL_SECS.into());
+
+    let url = match params.kind {
+        PlaybackKind::Api => {
+            playback::signed_stream_url(target_video.id, logged_user.id, expires_at).await
+        }
+        PlaybackKind::S3 => state
+            .storage
+            .presign_get(&target_video.bucket.to_string(), playback::URL_TTL_SECS)
+            .await
+            .map_err(|err| (err.status_code(), err.to_string()))?,
+        PlaybackKind::Hls => {
+            if target_video.master_playlist.is_none() {
+                return Err((
+                    StatusCode::CONFLICT,
+                    "The renditions of this video are not ready yet".to_string(),
+                ));
+            }
+
+            playback::signed_hls_url(target_video.id, logged_user.id, expires_at).await
+        }
+    };
+
+    Ok(Json(PlaybackUrl { url, expires_at }))
+}
+
+/// Requeue a video whose processing ran out of attempts
+async fn retry_processing(
+    State(state): State<AppState>,
+    Path(video_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::videos::dsl::videos;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = videos
+        .select(models::Video::as_select())
+        .find(video_id)
+        .filter(schema::videos::deleted_at.is_null())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .map_not_found()?;
+
+    if target_video.author_id != logged_user.id {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    let retried = jobs::retry(&mut conn, target_video.id)
+        .await
+        .map_err(errors::internal_error)?;
+
+    if !retried {
+        return Err((
+            StatusCode::CONFLICT,
+            "Video processing has not failed".to_string(),
+        ));
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(TryFromMultipart)]
+struct UploadThumbnailRequest {
+    #[form_data(limit = "10MiB")]
+    image: FieldData<NamedTempFile>,
+}
+
+/// Replace the thumbnail of a video with a custom image
+async fn upload_thumbnail(
+    State(state): State<AppState>,
+    Path(video_id): Path<i32>,
+    Extension(logged_user): Extension<models::User>,
+    TypedMultipart(upload_request): TypedMultipart<UploadThumbnailRequest>,
+) -> Result<Json<Vec<models::Thumbnail>>, (StatusCode, String)> {
+    use schema::videos::dsl::videos;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = videos
+        .select(models::Video::as_select())
+        .find(video_id)
+        .filter(schema::videos::deleted_at.is_null())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .map_not_found()?;
+
+    if target_video.author_id != logged_user.id {
+        return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+    }
+
+    let image_path = upload_request.image.contents.path().to_owned();
+
+    let images = tokio::task::spawn_blocking(move || {
+        let frame = video_util::decode_image(image_path).map_err(|_| {
+            (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "The file is not a supported image".to_string(),
+            )
+        })?;
+
+        thumbnail::encode_all(&frame).map_err(errors::internal_error)
+    })
+    .await
+    .map_err(errors::internal_error)??;
+
+    let new_thumbnail = thumbnail::upload(state.storage.as_ref(), target_video.bucket, images)
+        .await
+        .map_err(errors::internal_error)?;
+
+    let updated_video = diesel::update(videos.find(video_id))
+        .set(schema::videos::thumbnail.eq(new_thumbnail))
+        .returning(models::VIDEO_ALL_COLUMNS)
+        .get_result::<models::Video>(&mut conn)
+        .await
+        .map_err(errors::internal_error)?;
+
+    if let Some(old_thumbnail) = target_video.thumbnail {
+        if let Err(err) =
+            thumbnail::delete(state.storage.as_ref(), target_video.bucket, old_thumbnail).await
+        {
+            tracing::warn!(video_id, "cannot delete replaced thumbnail: {err}");
+        }
+    }
+
+    Ok(Json(updated_video.thumbnails()))
+}
+
+async fn get_thumbnail(
+    State(state): State<AppState>,
+    Path((video_id, thumbnail_id, size_name)): Path<(i32, uuid::Uuid, String)>,
+    auth::OptionalUser(logged_user): auth::OptionalUser,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    use schema::videos::dsl::videos;
+
+    let size = thumbnail::find_size(&size_name).map_not_found()?;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = videos
+        .select(models::Video::as_select())
+        .find(video_id)
+        .filter(schema::videos::deleted_at.is_null())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|video: &models::Video| {
+            video.thumbnail == Some(thumbnail_id) && video.is_visible_to(logged_user.as_ref())
+        })
+        .map_not_found()?;
+
+    let image = state
+        .storage
+        .get(&thumbnail::key(target_video.bucket, thumbnail_id, size))
+        .await
+        .map_err(|err| (err.status_code(), err.to_string()))?;
+
+    // A thumbnail id is never reused, so the image can be cached forever, but
+    // shared caches must not keep one that may stop being visible
+    let cache_control = if target_video.visibility == models::Visibility::Public {
+        "public, max-age=31536000, immutable"
+    } else {
+        "private, no-cache"
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, thumbnail::CONTENT_TYPE),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        image,
+    ))
 }
 
 #[derive(Debug, Deserialize)]
@@ -164,10 +1145,22 @@ async fn like_video(
     Extension(logged_user): Extension<models::User>,
     Json(like_video_query): Json<LikeVideoBody>,
 ) -> Result<(), (StatusCode, String)> {
-    use schema::likes::dsl::{is_liking, likes, user_id, video_id};
+    use schema::likes::dsl::{created_at, is_liking, likes, user_id, video_id};
+    use schema::videos::dsl::videos;
 
     let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
 
+    videos
+        .select(models::Video::as_select())
+        .find(target_video_id)
+        .filter(schema::videos::deleted_at.is_null())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|video| video.is_visible_to(Some(&logged_user)))
+        .map_not_found()?;
+
     if let Some(new_like) = like_video_query.likes {
         let existing_like = likes
             .select(models::Like::as_select())
@@ -192,7 +1185,7 @@ async fn like_video(
             diesel::update(likes)
                 .filter(video_id.eq(target_video_id))
                 .filter(user_id.eq(logged_user.id))
-                .set(is_liking.eq(new_like))
+                .set((is_liking.eq(new_like), created_at.eq(diesel::dsl::now)))
                 .execute(&mut conn)
                 .await
                 .map_err(errors::internal_error)?;
@@ -208,3 +1201,141 @@ async fn like_video(
 
     Ok(())
 }
+
+/// Heartbeat of the player, sent when playback starts and then regularly,
+/// which counts a view once the viewer has watched long enough
+async fn record_view(
+    State(state): State<AppState>,
+    Path(target_video_id): Path<i32>,
+    auth::OptionalUser(logged_user): auth::OptionalUser,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use schema::videos::dsl::videos;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    let target_video = videos
+        .select(models::Video::as_select())
+        .find(target_video_id)
+        .filter(schema::videos::deleted_at.is_null())
+        .filter(schema::videos::status.eq(models::VideoStatus::Ready))
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(errors::internal_error)?
+        .filter(|video| video.is_visible_to(logged_user.as_ref()))
+        .map_not_found()?;
+
+    let config = config::config().await;
+
+    let viewer = match &logged_user {
+        Some(user) => views::Viewer::User(user.id),
+        None => {
+            let address = Some(&headers)
+                .filter(|_| config.view_trust_forwarded_for())
+                .and_then(forwarded_for)
+                .unwrap_or(peer.ip());
+
+            let user_agent = headers
+                .get(header::USER_AGENT)
+                .and_then(|user_agent| user_agent.to_str().ok())
+                .unwrap_or_default();
+
+            state.views.anonymous_viewer(address, user_agent)
+        }
+    };
+
+    state.views.heartbeat(
+        viewer,
+        target_video.id,
+        std::time::Duration::from_secs(target_video.duration_seconds.max(0) as u64),
+        config.view_min_watch(),
+        config.view_dedup_window(),
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Client address as seen by the proxy in front of us, which appends it to
+/// whatever the client sent
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get("x-forwarded-for")?
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Likes of the video in the current row, which has no stats before its first
+/// like or view
+pub const LIKE_COUNT_SQL: &str = "coalesce(video_stats.like_count, 0)";
+
+/// Views of the video in the current row, see `LIKE_COUNT_SQL`
+pub const VIEW_COUNT_SQL: &str = "coalesce(video_stats.view_count, 0)";
+
+/// Videos after `(last_count, last_id)` when sorting by `count_sql` then by id,
+/// both descending
+pub fn after_count(
+    count_sql: &str,
+    last_count: i64,
+    last_id: i32,
+) -> diesel::expression::SqlLiteral<diesel::sql_types::Bool> {
+    // Integers can't inject anything
+    diesel::dsl::sql(&format!(
+        "({count_sql}, videos.id) < ({last_count}, {last_id})"
+    ))
+}
+
+/// Complete rows selected with `VIDEO_ALL_COLUMNS`, the author and the stats
+/// of each video into what every listing returns
+pub async fn videos_with_author(
+    conn: &mut diesel_async::AsyncPgConnection,
+    rows: Vec<(models::Video, models::User, Option<models::VideoStats>)>,
+    logged_user: Option<&models::User>,
+) -> QueryResult<Vec<models::VideoWithAuthor>> {
+    let video_ids = rows
+        .iter()
+        .map(|(video, _, _)| video.id)
+        .collect::<Vec<_>>();
+
+    let ratings = ratings(conn, logged_user, &video_ids).await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(video, author, stats)| models::VideoWithAuthor {
+            thumbnails: video.thumbnails(),
+            is_liking: ratings.get(&video.id).copied(),
+            highlight: None,
+            stats: stats.unwrap_or_default(),
+            video,
+            author,
+        })
+        .collect())
+}
+
+/// Ratings of `user` among `video_ids`, by video id
+async fn ratings(
+    conn: &mut diesel_async::AsyncPgConnection,
+    user: Option<&models::User>,
+    video_ids: &[i32],
+) -> QueryResult<HashMap<i32, bool>> {
+    use schema::likes::dsl::{is_liking, likes, user_id, video_id};
+
+    let Some(user) = user else {
+        return Ok(HashMap::new());
+    };
+
+    let user_likes = likes
+        .select((video_id, is_liking))
+        .filter(user_id.eq(user.id))
+        .filter(video_id.eq_any(video_ids))
+        .load::<(i32, bool)>(conn)
+        .await?;
+
+    Ok(user_likes.into_iter().collect())
+}
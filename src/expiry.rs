@@ -0,0 +1,30 @@
+//! Uploads which are started and never finished are removed once they
+//! expire, see `config::upload_expiry`: the files of resumable uploads and
+//! the parts of direct multipart uploads
+
+use crate::{controllers, AppState};
+
+use std::time::Duration;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Remove expired uploads forever
+pub async fn run(state: AppState) {
+    tracing::info!("upload expiry sweeper started");
+
+    loop {
+        match controllers::uploads::remove_expired(&state).await {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!(removed, "removed expired uploads"),
+            Err(err) => tracing::error!("cannot remove expired uploads: {err}"),
+        }
+
+        match controllers::direct_uploads::abort_expired(&state).await {
+            Ok(0) => {}
+            Ok(aborted) => tracing::info!(aborted, "aborted expired multipart uploads"),
+            Err(err) => tracing::error!("cannot abort expired multipart uploads: {err}"),
+        }
+
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+    }
+}
@@ -0,0 +1,212 @@
+//! Reconciliation of the object store with the database. Every object lives
+//! under the `bucket` id of a video (or of the multipart upload that will
+//! become one) or is the current avatar or banner of a user, anything else was
+//! left behind by a failed request.
+
+use crate::profile_image::ImageKind;
+use crate::{config, schema, thumbnail, AppState};
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+
+const COLLECT_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Counts of one collection, the objects themselves are logged
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Objects without an owner, past the grace period
+    pub orphans: usize,
+    /// How many of the orphans were deleted, none in dry-run mode
+    pub deleted: usize,
+    /// Objects rows point to which aren't there
+    pub missing: usize,
+}
+
+/// Collect garbage forever, as configured
+pub async fn run(state: AppState) {
+    tracing::info!("object garbage collector started");
+
+    loop {
+        let dry_run = config::config().await.gc_dry_run();
+
+        if let Err(err) = collect(&state, dry_run).await {
+            tracing::error!("cannot collect orphaned objects: {err}");
+        }
+
+        tokio::time::sleep(COLLECT_INTERVAL).await;
+    }
+}
+
+/// List the whole bucket, delete the orphans unless `dry_run` and report the
+/// videos with missing objects
+pub async fn collect(state: &AppState, dry_run: bool) -> Result<Report, BoxError> {
+    // Rows are loaded before listing, objects stored since then are protected
+    // by the grace period
+    let owners = load_owners(state).await?;
+
+    let grace_period = config::config().await.gc_grace_period();
+    let stored_before = chrono::Utc::now() - grace_period;
+
+    let objects = state.storage.list("").await?;
+
+    let mut orphans = Vec::new();
+
+    for object in &objects {
+        // Keep anything we can't date
+        let Some(last_modified) = object.last_modified else {
+            continue;
+        };
+
+        if last_modified < stored_before && !owners.owns(&object.key) {
+            orphans.push(object.key.as_str());
+        }
+    }
+
+    let mut report = Report {
+        orphans: orphans.len(),
+        ..Report::default()
+    };
+
+    for key in orphans {
+        tracing::info!(key, dry_run, "orphaned object");
+
+        if dry_run {
+            continue;
+        }
+
+        match state.storage.delete(key).await {
+            Ok(_) => report.deleted += 1,
+            Err(err) => tracing::warn!(key, "cannot delete orphaned object: {err}"),
+        }
+    }
+
+    let keys = objects
+        .iter()
+        .map(|object| object.key.as_str())
+        .collect::<HashSet<_>>();
+
+    for video in owners.videos.values() {
+        let expected =
+            std::iter::once(video.bucket.to_string()).chain(video.master_playlist.clone());
+
+        for key in expected {
+            if !keys.contains(key.as_str()) {
+                tracing::warn!(video_id = video.id, key, "missing object");
+
+                report.missing += 1;
+            }
+        }
+    }
+
+    tracing::info!(
+        orphans = report.orphans,
+        deleted = report.deleted,
+        missing = report.missing,
+        "object garbage collection done"
+    );
+
+    Ok(report)
+}
+
+#[derive(Debug, Queryable)]
+struct OwnerVideo {
+    id: i32,
+    bucket: uuid::Uuid,
+    master_playlist: Option<String>,
+    thumbnail: Option<uuid::Uuid>,
+}
+
+struct Owners {
+    videos: HashMap<uuid::Uuid, OwnerVideo>,
+    multipart_uploads: HashSet<uuid::Uuid>,
+    avatars: HashSet<uuid::Uuid>,
+    banners: HashSet<uuid::Uuid>,
+}
+
+impl Owners {
+    /// Whether `key` is the original of a video, a file below it other than a
+    /// replaced set of thumbnails, an upload in progress or a profile image
+    fn owns(&self, key: &str) -> bool {
+        let mut segments = key.split('/');
+
+        let Some(first_segment) = segments.next() else {
+            return false;
+        };
+
+        if let Some(kind) = ImageKind::from_prefix(first_segment) {
+            let images = match kind {
+                ImageKind::Avatar => &self.avatars,
+                ImageKind::Banner => &self.banners,
+            };
+
+            return segments
+                .next()
+                .and_then(|image| uuid::Uuid::parse_str(image).ok())
+                .is_some_and(|image| images.contains(&image));
+        }
+
+        let Ok(bucket) = uuid::Uuid::parse_str(first_segment) else {
+            return false;
+        };
+
+        if self.multipart_uploads.contains(&bucket) {
+            return true;
+        }
+
+        let Some(video) = self.videos.get(&bucket) else {
+            return false;
+        };
+
+        match (segments.next(), segments.next()) {
+            (Some(thumbnail::PREFIX), Some(thumbnail)) => {
+                uuid::Uuid::parse_str(thumbnail).ok() == video.thumbnail
+            }
+            _ => true,
+        }
+    }
+}
+
+async fn load_owners(state: &AppState) -> Result<Owners, BoxError> {
+    use schema::multipart_uploads;
+    use schema::users;
+    use schema::videos::dsl::{bucket, id, master_playlist, thumbnail, videos};
+
+    let mut conn = state.db_pool.get().await?;
+
+    let owner_videos = videos
+        .select((id, bucket, master_playlist, thumbnail))
+        .load::<OwnerVideo>(&mut conn)
+        .await?;
+
+    let multipart_uploads = multipart_uploads::table
+        .select(multipart_uploads::id)
+        .load::<uuid::Uuid>(&mut conn)
+        .await?;
+
+    let profile_images = users::table
+        .select((users::avatar, users::banner))
+        .filter(users::avatar.is_not_null().or(users::banner.is_not_null()))
+        .load::<(Option<uuid::Uuid>, Option<uuid::Uuid>)>(&mut conn)
+        .await?;
+
+    Ok(Owners {
+        videos: owner_videos
+            .into_iter()
+            .map(|video| (video.bucket, video))
+            .collect(),
+        multipart_uploads: multipart_uploads.into_iter().collect(),
+        avatars: profile_images
+            .iter()
+            .filter_map(|(avatar, _)| *avatar)
+            .collect(),
+        banners: profile_images
+            .iter()
+            .filter_map(|(_, banner)| *banner)
+            .collect(),
+    })
+}
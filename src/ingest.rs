@@ -0,0 +1,144 @@
+use crate::{errors, jobs, models, schema, video_util, AppState};
+
+use std::path::PathBuf;
+
+use axum::http::StatusCode;
+
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+
+const MAX_TITLE_LENGTH: usize = 100;
+
+const MAX_DESCRIPTION_LENGTH: usize = 5000;
+
+/// Check the title and description of a video, those given at least, listing
+/// every invalid field with the reason so a form can show them all at once.
+/// Every upload flow and the edition of a video use it, so that they all
+/// accept the same values.
+pub fn validate_details(
+    title: Option<&str>,
+    description: Option<&str>,
+) -> Result<(), (StatusCode, String)> {
+    let mut errors = Vec::new();
+
+    if let Some(title) = title {
+        if title.trim().is_empty() {
+            errors.push("title: must not be empty".to_string());
+        } else if title.chars().count() > MAX_TITLE_LENGTH {
+            errors.push(format!(
+                "title: must be at most {MAX_TITLE_LENGTH} characters"
+            ));
+        }
+    }
+
+    if let Some(description) = description {
+        if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+            errors.push(format!(
+                "description: must be at most {MAX_DESCRIPTION_LENGTH} characters"
+            ));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err((StatusCode::UNPROCESSABLE_ENTITY, errors.join("\n")))
+    }
+}
+
+/// What the author told us about a video they are uploading
+#[derive(Debug)]
+pub struct NewUpload {
+    pub title: String,
+    pub description: String,
+    pub author_id: i32,
+    pub visibility: models::Visibility,
+}
+
+/// Validate a fully received file, store it as the original of a new video
+/// and queue it for processing. Every upload flow ends up here.
+pub async fn ingest_file(
+    state: &AppState,
+    upload: NewUpload,
+    path: PathBuf,
+) -> Result<models::Video, (StatusCode, String)> {
+    let bucket_id = uuid::Uuid::new_v4();
+
+    // Reject anything we won't be able to process before storing it
+    let validated_video = validate(path.clone()).await?;
+
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .map_err(errors::internal_error)?;
+
+    state
+        .storage
+        .put_stream(
+            &bucket_id.to_string(),
+            &mut file,
+            "application/octet-stream",
+        )
+        .await
+        .map_err(errors::internal_error)?;
+
+    create_video(state, upload, bucket_id, validated_video).await
+}
+
+/// Run `video_util::validate_video` off the async workers. `input` may also be
+/// a URL ffmpeg knows how to read.
+pub async fn validate(input: PathBuf) -> Result<video_util::ValidatedVideo, (StatusCode, String)> {
+    tokio::task::spawn_blocking(move || video_util::validate_video(input))
+        .await
+        .map_err(errors::internal_error)?
+        .map_err(|err| (err.status_code(), err.to_string()))
+}
+
+/// Insert the video whose original is already stored under `bucket_id`, along
+/// with its metadata and processing job
+pub async fn create_video(
+    state: &AppState,
+    upload: NewUpload,
+    bucket_id: uuid::Uuid,
+    validated_video: video_util::ValidatedVideo,
+) -> Result<models::Video, (StatusCode, String)> {
+    use schema::videos::dsl::videos;
+
+    let mut conn = state.db_pool.get().await.map_err(errors::internal_error)?;
+
+    // Renditions are filled in by the processing worker
+    let new_video = models::NewVideo {
+        title: upload.title,
+        description: upload.description,
+        duration_seconds: validated_video.duration.num_seconds(),
+        bucket: bucket_id,
+        author_id: upload.author_id,
+        master_playlist: None,
+        visibility: upload.visibility,
+    };
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            let inserted_video: models::Video = diesel::insert_into(videos)
+                .values(&new_video)
+                .returning(models::VIDEO_ALL_COLUMNS)
+                .get_result(conn)
+                .await?;
+
+            diesel::insert_into(schema::video_metadata::table)
+                .values((
+                    schema::video_metadata::video_id.eq(inserted_video.id),
+                    &validated_video.metadata,
+                ))
+                .execute(conn)
+                .await?;
+
+            jobs::enqueue(conn, inserted_video.id).await?;
+
+            Ok(inserted_video)
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(errors::internal_error)
+}
@@ -0,0 +1,239 @@
+use crate::models::{Job, JobState, VideoStatus};
+use crate::schema;
+
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+
+/// How often a worker refreshes the lock of the job it is running
+pub const LOCK_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A job whose lock wasn't refreshed for this long is assumed to belong to a
+/// crashed worker and becomes claimable again
+const STALE_LOCK_MINUTES: i32 = 5;
+
+const BACKOFF_BASE_SECONDS: i64 = 30;
+const BACKOFF_MAX_SECONDS: i64 = 60 * 60;
+
+/// Queue processing of a freshly uploaded video
+pub async fn enqueue(conn: &mut AsyncPgConnection, target_video_id: i32) -> QueryResult<Job> {
+    use schema::jobs::dsl::{jobs, video_id};
+
+    diesel::insert_into(jobs)
+        .values(video_id.eq(target_video_id))
+        .returning(Job::as_returning())
+        .get_result(conn)
+        .await
+}
+
+/// What a running job learns when refreshing its lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heartbeat {
+    Held,
+    /// Another worker reclaimed the job
+    Lost,
+    /// The video went to the trash, processing it is wasted work
+    VideoDeleted,
+}
+
+/// Atomically take the next runnable job, if any, and mark its video as
+/// processing. Videos in the trash wait until they are restored.
+pub async fn claim_next(conn: &mut AsyncPgConnection) -> QueryResult<Option<Job>> {
+    let claimed = diesel::sql_query(
+        "update jobs set state = 'running', locked_at = now(), attempts = attempts + 1
+         where id = (
+           select id from jobs
+           where ((state = 'pending' and run_at <= now())
+              or (state = 'running' and locked_at < now() - $1 * interval '1 minute'))
+             and not exists (
+               select 1 from videos
+               where videos.id = jobs.video_id and videos.deleted_at is not null
+             )
+           order by run_at
+           for update skip locked
+           limit 1
+         )
+         returning *",
+    )
+    .bind::<diesel::sql_types::Integer, _>(STALE_LOCK_MINUTES)
+    .get_result::<Job>(conn)
+    .await
+    .optional()?;
+
+    if let Some(job) = &claimed {
+        set_video_status(conn, job.video_id, VideoStatus::Processing).await?;
+    }
+
+    Ok(claimed)
+}
+
+/// Keep the lock of a running job, which is ours as long as nobody claimed it
+/// again since, which would have counted another attempt
+pub async fn heartbeat(conn: &mut AsyncPgConnection, job: &Job) -> QueryResult<Heartbeat> {
+    use schema::jobs::dsl::{attempts, jobs, locked_at, state};
+    use schema::videos::dsl::{deleted_at, videos};
+
+    let refreshed = diesel::update(jobs.find(job.id))
+        .filter(state.eq(JobState::Running))
+        .filter(attempts.eq(job.attempts))
+        .set(locked_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await?;
+
+    if refreshed == 0 {
+        return Ok(Heartbeat::Lost);
+    }
+
+    let deleted = videos
+        .find(job.video_id)
+        .select(deleted_at.is_not_null())
+        .first::<bool>(conn)
+        .await?;
+
+    Ok(if deleted {
+        Heartbeat::VideoDeleted
+    } else {
+        Heartbeat::Held
+    })
+}
+
+/// Put a job back in the queue without counting the attempt, for a video
+/// trashed while it was processed
+pub async fn release(conn: &mut AsyncPgConnection, job: &Job) -> QueryResult<()> {
+    use schema::jobs::dsl::{attempts, jobs, locked_at, state};
+
+    diesel::update(jobs.find(job.id))
+        .filter(attempts.eq(job.attempts))
+        .set((
+            state.eq(JobState::Pending),
+            attempts.eq(job.attempts - 1),
+            locked_at.eq(None::<chrono::DateTime<chrono::Utc>>),
+        ))
+        .execute(conn)
+        .await?;
+
+    set_video_status(conn, job.video_id, VideoStatus::Uploaded).await
+}
+
+/// Mark a job as done and its video as ready. Returns `false`, changing
+/// nothing, if the job was reclaimed by another worker in the meantime.
+pub async fn complete(conn: &mut AsyncPgConnection, job: &Job) -> QueryResult<bool> {
+    use schema::jobs::dsl::{attempts, jobs, last_error, locked_at, state};
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            let updated = diesel::update(jobs.find(job.id))
+                .filter(state.eq(JobState::Running))
+                .filter(attempts.eq(job.attempts))
+                .set((
+                    state.eq(JobState::Done),
+                    last_error.eq(None::<String>),
+                    locked_at.eq(None::<chrono::DateTime<chrono::Utc>>),
+                ))
+                .execute(conn)
+                .await?;
+
+            if updated == 0 {
+                return Ok(false);
+            }
+
+            set_video_status(conn, job.video_id, VideoStatus::Ready).await?;
+
+            Ok(true)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Record the failure of an attempt, scheduling a retry with exponential
+/// backoff until the job runs out of attempts. Returns `None`, changing
+/// nothing, if the job was reclaimed by another worker in the meantime.
+pub async fn fail(
+    conn: &mut AsyncPgConnection,
+    job: &Job,
+    error: &str,
+) -> QueryResult<Option<JobState>> {
+    use schema::jobs::dsl::{attempts, jobs, last_error, locked_at, run_at, state};
+
+    let (new_state, video_status) = if job.attempts >= job.max_attempts {
+        (JobState::Failed, VideoStatus::Failed)
+    } else {
+        (JobState::Pending, VideoStatus::Uploaded)
+    };
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            let updated = diesel::update(jobs.find(job.id))
+                .filter(state.eq(JobState::Running))
+                .filter(attempts.eq(job.attempts))
+                .set((
+                    state.eq(new_state),
+                    last_error.eq(error),
+                    locked_at.eq(None::<chrono::DateTime<chrono::Utc>>),
+                    run_at.eq(chrono::Utc::now() + backoff(job.attempts)),
+                ))
+                .execute(conn)
+                .await?;
+
+            if updated == 0 {
+                return Ok(None);
+            }
+
+            set_video_status(conn, job.video_id, video_status).await?;
+
+            Ok(Some(new_state))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Give a failed video a fresh set of attempts. Returns `false` if there was
+/// no failed job to retry.
+pub async fn retry(conn: &mut AsyncPgConnection, target_video_id: i32) -> QueryResult<bool> {
+    use schema::jobs::dsl::{attempts, jobs, run_at, state, video_id};
+
+    let updated = diesel::update(jobs)
+        .filter(video_id.eq(target_video_id))
+        .filter(state.eq(JobState::Failed))
+        .set((
+            state.eq(JobState::Pending),
+            attempts.eq(0),
+            run_at.eq(chrono::Utc::now()),
+        ))
+        .execute(conn)
+        .await?;
+
+    if updated == 0 {
+        return Ok(false);
+    }
+
+    set_video_status(conn, target_video_id, VideoStatus::Uploaded).await?;
+
+    Ok(true)
+}
+
+async fn set_video_status(
+    conn: &mut AsyncPgConnection,
+    target_video_id: i32,
+    new_status: VideoStatus,
+) -> QueryResult<()> {
+    use schema::videos::dsl::{status, videos};
+
+    diesel::update(videos.find(target_video_id))
+        .set(status.eq(new_status))
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+fn backoff(attempts: i32) -> chrono::Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    let seconds = BACKOFF_BASE_SECONDS.saturating_mul(2_i64.pow(exponent));
+
+    chrono::Duration::seconds(seconds.min(BACKOFF_MAX_SECONDS))
+}
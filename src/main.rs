@@ -3,13 +3,29 @@ mod config;
 mod controllers;
 mod db;
 mod errors;
+mod expiry;
+mod gc;
+mod ingest;
+mod jobs;
 mod models;
+mod pagination;
+mod playback;
+mod presign;
+mod profile_image;
 mod schema;
+mod storage;
+mod streaming;
+mod thumbnail;
+mod transcode;
+mod trash;
 mod video_util;
+mod views;
+mod worker;
 
 extern crate ffmpeg_next as ffmpeg;
 
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 use axum::{extract::State, http::StatusCode, routing::get, Router};
 
@@ -20,7 +36,8 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub db_pool: db::Pool,
-    pub s3: s3::Bucket,
+    pub storage: Arc<dyn storage::Storage>,
+    pub views: Arc<views::ViewCounter>,
 }
 
 #[tokio::main]
@@ -37,68 +54,81 @@ async fn main() {
 
     let app_state = AppState {
         db_pool: create_database_pool().await,
-        s3: create_s3_bucket().await,
+        storage: storage::from_config().await,
+        views: Arc::default(),
     };
 
+    let config = config::config().await;
+
+    // `youtube worker` only processes videos, without serving the API
+    // `youtube gc [--dry-run]` reconciles the object store once and exits
+    if std::env::args().nth(1).as_deref() == Some("gc") {
+        let dry_run = config.gc_dry_run() || std::env::args().any(|arg| arg == "--dry-run");
+
+        if let Err(err) = gc::collect(&app_state, dry_run).await {
+            tracing::error!("cannot collect orphaned objects: {err}");
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("worker") {
+        tokio::spawn(trash::run(app_state.clone()));
+        tokio::spawn(gc::run(app_state.clone()));
+        worker::run(app_state).await;
+        return;
+    }
+
+    if config.worker_enabled() {
+        tokio::spawn(worker::run(app_state.clone()));
+        tokio::spawn(trash::run(app_state.clone()));
+        tokio::spawn(gc::run(app_state.clone()));
+    }
+
+    // Views are buffered by the process serving the API
+    tokio::spawn(views::run(app_state.clone()));
+
+    // Resumable uploads are written to the disk of the process serving the API
+    tokio::spawn(expiry::run(app_state.clone()));
+
     let app = Router::new()
         .route("/health", get(health))
         .merge(controllers::auth::router(app_state.clone()))
+        .merge(controllers::profile::router(app_state.clone()))
+        .merge(controllers::history::router(app_state.clone()))
         .nest("/videos", controllers::videos::router(app_state.clone()))
+        .nest(
+            "/videos/:id/comments",
+            controllers::comments::router(app_state.clone()),
+        )
+        .nest(
+            "/videos/uploads",
+            controllers::uploads::router(app_state.clone()),
+        )
+        .nest(
+            "/videos/direct-uploads",
+            controllers::direct_uploads::router(app_state.clone()),
+        )
+        .nest("/users", controllers::users::router(app_state.clone()))
+        .nest("/feed", controllers::feed::router(app_state.clone()))
+        .nest(
+            "/playlists",
+            controllers::playlists::router(app_state.clone()),
+        )
         .with_state(app_state);
 
-    let config = config::config().await;
-
     let addr = format!("{}:{}", config.server_host(), config.server_port());
     let addr: SocketAddr = addr.parse().expect("invalid socket address");
     tracing::info!("listening on {addr}");
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-}
-
-async fn create_s3_bucket() -> s3::Bucket {
-    let config = config::config().await;
-
-    let region = s3::Region::Custom {
-        region: "eu-central-1".to_owned(),
-        endpoint: config.s3_base_url().to_owned(),
-    };
-
-    let credentials = s3::creds::Credentials::new(
-        Some(config.s3_access_key()),
-        Some(config.s3_secret_key()),
-        None,
-        None,
-        None,
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
     )
-    .expect("invalid S3 credentials");
-
-    let mut bucket = s3::Bucket::new(config.s3_bucket(), region.clone(), credentials.clone())
-        .expect("cannot access S3 bucket")
-        .with_path_style();
-
-    if !bucket
-        .exists()
-        .await
-        .expect("cannot check if bucket exists")
-    {
-        tracing::info!(
-            "bucket {} does not exist, creating it...",
-            config.s3_bucket()
-        );
-
-        bucket = s3::Bucket::create_with_path_style(
-            config.s3_bucket(),
-            region,
-            credentials,
-            s3::BucketConfiguration::default(),
-        )
-        .await
-        .expect("cannot create bucket")
-        .bucket;
-    }
-
-    bucket
+    .await
+    .unwrap();
 }
 
 async fn create_database_pool() -> db::Pool {
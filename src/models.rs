@@ -1,7 +1,74 @@
+use crate::profile_image::{self, ImageKind};
 use crate::schema::*;
+use crate::thumbnail;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, diesel_derive_enum::DbEnum, Serialize, Deserialize)]
+#[ExistingTypePath = "crate::schema::sql_types::VideoStatus"]
+#[serde(rename_all = "snake_case")]
+pub enum VideoStatus {
+    /// Stored, waiting for the processing worker
+    Uploaded,
+    Processing,
+    Ready,
+    Failed,
+}
+
+/// Who can see a video
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, diesel_derive_enum::DbEnum, Serialize, Deserialize,
+)]
+#[ExistingTypePath = "crate::schema::sql_types::Visibility"]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    #[default]
+    Public,
+    /// Reachable by anyone with the link, but never listed nor searchable
+    Unlisted,
+    /// Only visible to its author
+    Private,
+}
+
+impl std::str::FromStr for Visibility {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "public" => Ok(Visibility::Public),
+            "unlisted" => Ok(Visibility::Unlisted),
+            "private" => Ok(Visibility::Private),
+            _ => Err(format!("Unknown visibility: {value}")),
+        }
+    }
+}
+
+impl Visibility {
+    /// Whether `user`, `None` when anonymous, may access something owned by
+    /// `owner_id` with this visibility
+    pub fn allows(self, owner_id: i32, user: Option<&User>) -> bool {
+        self.allows_user_id(owner_id, user.map(|user| user.id))
+    }
+
+    /// Same as `allows`, when only the id of the user is known
+    pub fn allows_user_id(self, owner_id: i32, user_id: Option<i32>) -> bool {
+        match self {
+            Visibility::Public | Visibility::Unlisted => true,
+            Visibility::Private => user_id == Some(owner_id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, diesel_derive_enum::DbEnum, Serialize, Deserialize)]
+#[ExistingTypePath = "crate::schema::sql_types::JobState"]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
 #[derive(Debug, Clone, Queryable, Selectable, Identifiable, Serialize, Deserialize)]
 #[diesel(table_name = crate::schema::users)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -11,6 +78,75 @@ pub struct User {
 
     #[serde(skip_serializing)]
     pub password: String,
+
+    #[serde(skip_deserializing)]
+    pub subscriber_count: i64,
+
+    /// Shown instead of the username when set
+    pub display_name: Option<String>,
+    pub bio: String,
+
+    /// Never contains null, arrays just can't say so in the schema
+    pub links: Vec<Option<String>>,
+
+    #[serde(
+        rename = "avatars",
+        serialize_with = "serialize_avatar",
+        skip_deserializing
+    )]
+    pub avatar: Option<uuid::Uuid>,
+
+    #[serde(
+        rename = "banners",
+        serialize_with = "serialize_banner",
+        skip_deserializing
+    )]
+    pub banner: Option<uuid::Uuid>,
+
+    /// Whether watching stops being recorded, only told to the user through
+    /// their history
+    #[serde(skip)]
+    pub history_paused: bool,
+}
+
+/// Every size of a profile image, shaped like the thumbnails of a video
+fn profile_images(kind: ImageKind, image: Option<uuid::Uuid>) -> Vec<Thumbnail> {
+    let Some(image) = image else {
+        return Vec::new();
+    };
+
+    kind.sizes()
+        .iter()
+        .map(|size| Thumbnail {
+            size: size.name,
+            width: size.width,
+            height: size.height,
+            url: profile_image::url(kind, image, size),
+        })
+        .collect()
+}
+
+fn serialize_avatar<S: serde::Serializer>(
+    avatar: &Option<uuid::Uuid>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    profile_images(ImageKind::Avatar, *avatar).serialize(serializer)
+}
+
+fn serialize_banner<S: serde::Serializer>(
+    banner: &Option<uuid::Uuid>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    profile_images(ImageKind::Banner, *banner).serialize(serializer)
+}
+
+/// Profile fields a user may edit, `None` leaves a field unchanged
+#[derive(Debug, Default, AsChangeset)]
+#[diesel(table_name = crate::schema::users)]
+pub struct ProfileChanges {
+    pub display_name: Option<Option<String>>,
+    pub bio: Option<String>,
+    pub links: Option<Vec<Option<String>>>,
 }
 
 #[derive(Debug, Queryable, Selectable, Identifiable, Associations, Serialize, Deserialize)]
@@ -28,6 +164,66 @@ pub struct Video {
     pub published_at: chrono::DateTime<chrono::Utc>,
 
     pub author_id: i32,
+
+    /// Storage key of the HLS master playlist, once the renditions have been
+    /// produced. Clients play it through `hls_url` instead.
+    #[serde(skip_serializing)]
+    pub master_playlist: Option<String>,
+
+    pub status: VideoStatus,
+
+    /// Id of the current set of thumbnails, see `thumbnail::key`
+    pub thumbnail: Option<uuid::Uuid>,
+
+    pub visibility: Visibility,
+
+    #[serde(skip_deserializing)]
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+
+    /// Set while the video is in the trash, see `trash`
+    #[serde(skip_deserializing)]
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Whether new comments are accepted, existing ones stay visible
+    pub comments_enabled: bool,
+
+    /// Comment listed before all the others
+    pub pinned_comment_id: Option<i32>,
+}
+
+impl Video {
+    /// Whether `user`, `None` when anonymous, may access this video. Unlisted
+    /// videos are accessible, they are only kept out of listings.
+    pub fn is_visible_to(&self, user: Option<&User>) -> bool {
+        self.visibility.allows(self.author_id, user)
+    }
+
+    pub fn thumbnails(&self) -> Vec<Thumbnail> {
+        let Some(thumbnail) = self.thumbnail else {
+            return Vec::new();
+        };
+
+        thumbnail::SIZES
+            .iter()
+            .map(|size| Thumbnail {
+                size: size.name,
+                width: size.width,
+                height: size.height,
+                url: format!("/videos/{}/thumbnails/{thumbnail}/{}", self.id, size.name),
+            })
+            .collect()
+    }
+
+    /// API URL of the HLS master playlist, once the renditions have been produced
+    pub fn hls_url(&self) -> Option<String> {
+        self.master_playlist.as_ref().map(|_| {
+            format!(
+                "/videos/{}/hls/{}",
+                self.id,
+                crate::transcode::MASTER_PLAYLIST
+            )
+        })
+    }
 }
 
 /// We literally never want to select `textsearchable_index_col`
@@ -40,6 +236,14 @@ type VideoAllColumns = (
     videos::bucket,
     videos::published_at,
     videos::author_id,
+    videos::master_playlist,
+    videos::status,
+    videos::thumbnail,
+    videos::visibility,
+    videos::updated_at,
+    videos::deleted_at,
+    videos::comments_enabled,
+    videos::pinned_comment_id,
 );
 
 pub const VIDEO_ALL_COLUMNS: VideoAllColumns = (
@@ -50,6 +254,14 @@ pub const VIDEO_ALL_COLUMNS: VideoAllColumns = (
     videos::bucket,
     videos::published_at,
     videos::author_id,
+    videos::master_playlist,
+    videos::status,
+    videos::thumbnail,
+    videos::visibility,
+    videos::updated_at,
+    videos::deleted_at,
+    videos::comments_enabled,
+    videos::pinned_comment_id,
 );
 
 #[derive(Debug, Insertable)]
@@ -60,6 +272,18 @@ pub struct NewVideo {
     pub bucket: uuid::Uuid,
     pub duration_seconds: i64,
     pub author_id: i32,
+    pub master_playlist: Option<String>,
+    pub visibility: Visibility,
+}
+
+/// Fields of a video its author may edit, `None` leaves a field unchanged
+#[derive(Debug, Default, AsChangeset)]
+#[diesel(table_name = crate::schema::videos)]
+pub struct VideoChanges {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub visibility: Option<Visibility>,
+    pub comments_enabled: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
@@ -69,11 +293,107 @@ pub struct UserWithVideos {
     pub videos: Vec<Video>,
 }
 
+/// Public view of a user, see `GET /users/:id`
+#[derive(Debug, Serialize)]
+pub struct Channel {
+    #[serde(flatten)]
+    pub user: User,
+
+    /// Whether the logged user subscribed to the channel
+    pub is_subscribed: Option<bool>,
+
+    /// Views of all the public videos of the channel
+    pub total_views: i64,
+
+    pub videos: crate::pagination::Page<VideoWithAuthor>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct VideoWithAuthor {
     #[serde(flatten)]
     pub video: Video,
     pub author: User,
+    pub thumbnails: Vec<Thumbnail>,
+
+    #[serde(flatten)]
+    pub stats: VideoStats,
+
+    /// How the logged user rated the video, `true` for a like
+    pub is_liking: Option<bool>,
+
+    /// Why the video matched, only in search results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<SearchHighlight>,
+}
+
+/// Title and description with the matched words between the requested
+/// markers, STX and ETX by default. The text is not escaped, so it must be
+/// rendered as text and never as HTML.
+#[derive(Debug, Serialize)]
+pub struct SearchHighlight {
+    pub title: String,
+    pub description: String,
+}
+
+/// `width` and `height` bound the image, which keeps the aspect ratio of the
+/// video
+#[derive(Debug, Serialize)]
+pub struct Thumbnail {
+    pub size: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub url: String,
+}
+
+/// Technical properties of the original upload, as reported by ffmpeg
+#[derive(Debug, Clone, Queryable, Selectable, Insertable, Serialize)]
+#[diesel(table_name = video_metadata)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MediaMetadata {
+    /// Demuxer short name(s), e.g. `mov,mp4,m4a,3gp,3g2,mj2`
+    pub container: String,
+    pub width: i32,
+    pub height: i32,
+    pub frame_rate: Option<f64>,
+    pub video_codec: String,
+    pub audio_codec: Option<String>,
+    pub audio_channel_layout: Option<String>,
+
+    /// Overall bitrate in bits per second
+    pub bit_rate: Option<i64>,
+
+    /// Clockwise rotation in degrees players should apply
+    pub rotation: i32,
+
+    /// Whether the file has a subtitle stream
+    pub has_captions: bool,
+}
+
+/// Counters of a video, a video without any has no row yet. Likes are counted
+/// by triggers, views by `views`.
+#[derive(Debug, Clone, Default, Queryable, Selectable, Serialize)]
+#[diesel(table_name = video_stats)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct VideoStats {
+    pub like_count: i64,
+    pub dislike_count: i64,
+    pub view_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VideoDetails {
+    #[serde(flatten)]
+    pub video: Video,
+    pub metadata: Option<MediaMetadata>,
+
+    /// See `Video::hls_url`
+    pub hls_url: Option<String>,
+
+    #[serde(flatten)]
+    pub stats: VideoStats,
+
+    /// How the logged user rated the video, `true` for a like
+    pub is_liking: Option<bool>,
 }
 
 #[derive(Identifiable, Selectable, Queryable, Associations, Debug)]
@@ -86,3 +406,193 @@ pub struct Like {
     pub video_id: i32,
     pub is_liking: bool,
 }
+
+#[derive(Debug, Queryable, Selectable, Identifiable, Associations, Serialize)]
+#[diesel(belongs_to(User, foreign_key = author_id))]
+#[diesel(belongs_to(Video))]
+#[diesel(table_name = comments)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Comment {
+    pub id: i32,
+    pub video_id: i32,
+    pub author_id: i32,
+
+    /// The comment this one replies to, which is never a reply itself
+    pub parent_id: Option<i32>,
+
+    pub content: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommentWithAuthor {
+    #[serde(flatten)]
+    pub comment: Comment,
+    pub author: User,
+    pub likes: i64,
+    pub dislikes: i64,
+
+    /// Always 0 for replies
+    pub reply_count: i64,
+}
+
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug)]
+#[diesel(belongs_to(User))]
+#[diesel(belongs_to(Comment))]
+#[diesel(table_name = comment_likes)]
+#[diesel(primary_key(user_id, comment_id))]
+pub struct CommentLike {
+    pub user_id: i32,
+    pub comment_id: i32,
+    pub is_liking: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    pub video: VideoWithAuthor,
+
+    /// Where to resume the video
+    pub position_seconds: i64,
+
+    /// `position_seconds` over the duration of the video, from 0 to 1
+    pub watched_fraction: f64,
+
+    pub watched_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct History {
+    /// Whether watching is currently not recorded
+    pub paused: bool,
+
+    #[serde(flatten)]
+    pub entries: crate::pagination::Page<HistoryEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, diesel_derive_enum::DbEnum, Serialize)]
+#[ExistingTypePath = "crate::schema::sql_types::PlaylistKind"]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistKind {
+    /// Made by its owner
+    Custom,
+    /// Built in, always private and created on first use
+    WatchLater,
+}
+
+#[derive(Debug, Queryable, Selectable, Identifiable, Associations, Serialize)]
+#[diesel(belongs_to(User, foreign_key = owner_id))]
+#[diesel(table_name = playlists)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Playlist {
+    pub id: i32,
+    pub owner_id: i32,
+    pub kind: PlaylistKind,
+    pub title: String,
+    pub description: String,
+    pub visibility: Visibility,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Playlist {
+    pub fn is_visible_to(&self, user: Option<&User>) -> bool {
+        self.visibility.allows(self.owner_id, user)
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = playlists)]
+pub struct NewPlaylist {
+    pub owner_id: i32,
+    pub kind: PlaylistKind,
+    pub title: String,
+    pub description: String,
+    pub visibility: Visibility,
+}
+
+/// Fields of a playlist its owner may edit, `None` leaves a field unchanged
+#[derive(Debug, Default, AsChangeset)]
+#[diesel(table_name = playlists)]
+pub struct PlaylistChanges {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub visibility: Option<Visibility>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistItem {
+    pub position: i32,
+
+    #[serde(flatten)]
+    pub video: VideoWithAuthor,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistWithItems {
+    #[serde(flatten)]
+    pub playlist: Playlist,
+    pub owner: User,
+
+    /// Videos the caller may see, in playlist order
+    pub items: crate::pagination::Page<PlaylistItem>,
+}
+
+/// A resumable upload, whose data is appended to a file in the upload
+/// directory until `upload_offset` reaches `upload_length`
+#[derive(Debug, Queryable, Selectable, Identifiable, Associations)]
+#[diesel(belongs_to(User))]
+#[diesel(table_name = uploads)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Upload {
+    pub id: uuid::Uuid,
+    pub user_id: i32,
+    pub title: String,
+    pub description: String,
+    pub upload_length: i64,
+    pub upload_offset: i64,
+
+    /// Set once the upload is complete and has been ingested
+    pub video_id: Option<i32>,
+
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+
+    pub visibility: Visibility,
+}
+
+/// An S3 multipart upload the client sends directly to the object store.
+/// `id` is also the bucket of the video it will become.
+#[derive(Debug, Queryable, Selectable, Identifiable, Associations)]
+#[diesel(belongs_to(User))]
+#[diesel(table_name = multipart_uploads)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MultipartUpload {
+    pub id: uuid::Uuid,
+    pub user_id: i32,
+    pub s3_upload_id: String,
+    pub title: String,
+    pub description: String,
+    pub size: i64,
+    pub part_size: i64,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub visibility: Visibility,
+}
+
+#[derive(Debug, Queryable, QueryableByName, Selectable, Identifiable, Associations)]
+#[diesel(belongs_to(Video))]
+#[diesel(table_name = jobs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Job {
+    pub id: i32,
+    pub video_id: i32,
+    pub state: JobState,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+    pub run_at: chrono::DateTime<chrono::Utc>,
+    pub locked_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
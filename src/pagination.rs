@@ -0,0 +1,66 @@
+//! Keyset pagination with opaque cursors. A cursor is the sort key of the
+//! last item of a page, which the next page starts right after, so pages
+//! don't shift when rows are inserted in the meantime.
+
+use axum::http::StatusCode;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    /// `next_cursor` of the previous page, none for the first one
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+impl PageQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    pub fn cursor<K: DeserializeOwned>(&self) -> Result<Option<K>, (StatusCode, String)> {
+        self.cursor.as_deref().map(decode_cursor).transpose()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+
+    /// Absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Build a page out of `limit + 1` rows at most, the extra one only tells
+    /// whether there is a next page
+    pub fn from_rows<K: Serialize>(mut items: Vec<T>, limit: i64, key: impl Fn(&T) -> K) -> Self {
+        let has_more = items.len() as i64 > limit;
+        items.truncate(limit as usize);
+
+        let next_cursor = items
+            .last()
+            .filter(|_| has_more)
+            .map(|last| encode_cursor(&key(last)));
+
+        Page { items, next_cursor }
+    }
+}
+
+pub fn encode_cursor<K: Serialize>(key: &K) -> String {
+    let json = serde_json::to_vec(key).expect("cursor keys are serializable");
+
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json)
+}
+
+pub fn decode_cursor<K: DeserializeOwned>(cursor: &str) -> Result<K, (StatusCode, String)> {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid cursor".to_string()))
+}
@@ -0,0 +1,256 @@
+//! Time-limited playback URLs, usable by a `<video>` element that cannot send
+//! an `Authorization` header
+
+use crate::config;
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+/// How long a playback URL stays valid
+pub const URL_TTL_SECS: u32 = 4 * 60 * 60;
+
+/// What a signed URL gives access to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// `/videos/:id/stream`, the original
+    Stream,
+    /// `/videos/:id/hls/*`, every playlist and segment of the renditions
+    Hls,
+}
+
+impl Resource {
+    fn name(self) -> &'static str {
+        match self {
+            Resource::Stream => "stream",
+            Resource::Hls => "hls",
+        }
+    }
+}
+
+/// Query parameters of a signed URL. Requests made with it are treated as
+/// coming from `user`.
+#[derive(Debug, Deserialize)]
+pub struct StreamSignature {
+    pub expires: i64,
+    pub user: i32,
+    pub signature: String,
+}
+
+impl StreamSignature {
+    async fn new(
+        video_id: i32,
+        resource: Resource,
+        user_id: i32,
+        expires_at: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        let secret = config::config().await.playback_secret();
+
+        Self::with_secret(secret, video_id, resource, user_id, expires_at)
+    }
+
+    fn with_secret(
+        secret: &str,
+        video_id: i32,
+        resource: Resource,
+        user_id: i32,
+        expires_at: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        let expires = expires_at.timestamp();
+        let signature = hex::encode(
+            mac(secret, video_id, resource, user_id, expires)
+                .finalize()
+                .into_bytes(),
+        );
+
+        Self {
+            expires,
+            user: user_id,
+            signature,
+        }
+    }
+
+    /// The signature as a URL query, without the leading `?`
+    pub fn query(&self) -> String {
+        format!(
+            "expires={}&user={}&signature={}",
+            self.expires, self.user, self.signature
+        )
+    }
+}
+
+/// API URL streaming `video_id` to `user_id` until `expires_at`
+pub async fn signed_stream_url(
+    video_id: i32,
+    user_id: i32,
+    expires_at: chrono::DateTime<chrono::Utc>,
+) -> String {
+    let signature = StreamSignature::new(video_id, Resource::Stream, user_id, expires_at).await;
+
+    format!("/videos/{video_id}/stream?{}", signature.query())
+}
+
+/// API URL of the HLS master playlist of `video_id` for `user_id` until
+/// `expires_at`. The playlists it serves carry the signature along.
+pub async fn signed_hls_url(
+    video_id: i32,
+    user_id: i32,
+    expires_at: chrono::DateTime<chrono::Utc>,
+) -> String {
+    let signature = StreamSignature::new(video_id, Resource::Hls, user_id, expires_at).await;
+
+    format!(
+        "/videos/{video_id}/hls/{}?{}",
+        crate::transcode::MASTER_PLAYLIST,
+        signature.query()
+    )
+}
+
+/// Whether `signature` was issued by us for this resource of the video and
+/// hasn't expired yet
+pub async fn verify(video_id: i32, resource: Resource, signature: &StreamSignature) -> bool {
+    let secret = config::config().await.playback_secret();
+
+    verify_with_secret(secret, video_id, resource, signature, chrono::Utc::now())
+}
+
+fn verify_with_secret(
+    secret: &str,
+    video_id: i32,
+    resource: Resource,
+    signature: &StreamSignature,
+    now: chrono::DateTime<chrono::Utc>,
+) -> bool {
+    if signature.expires < now.timestamp() {
+        return false;
+    }
+
+    let Ok(raw_signature) = hex::decode(&signature.signature) else {
+        return false;
+    };
+
+    mac(
+        secret,
+        video_id,
+        resource,
+        signature.user,
+        signature.expires,
+    )
+    .verify_slice(&raw_signature)
+    .is_ok()
+}
+
+fn mac(
+    secret: &str,
+    video_id: i32,
+    resource: Resource,
+    user_id: i32,
+    expires: i64,
+) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{video_id}:{}:{user_id}:{expires}", resource.name()).as_bytes());
+
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test secret";
+    const VIDEO_ID: i32 = 42;
+    const USER_ID: i32 = 7;
+
+    fn now() -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    fn signed(resource: Resource) -> StreamSignature {
+        let expires_at = now() + chrono::Duration::seconds(URL_TTL_SECS.into());
+
+        StreamSignature::with_secret(SECRET, VIDEO_ID, resource, USER_ID, expires_at)
+    }
+
+    fn verifies(video_id: i32, resource: Resource, signature: &StreamSignature) -> bool {
+        verify_with_secret(SECRET, video_id, resource, signature, now())
+    }
+
+    #[test]
+    fn accepts_its_own_signatures() {
+        assert!(verifies(
+            VIDEO_ID,
+            Resource::Stream,
+            &signed(Resource::Stream)
+        ));
+        assert!(verifies(VIDEO_ID, Resource::Hls, &signed(Resource::Hls)));
+    }
+
+    #[test]
+    fn rejects_expired_signatures() {
+        let signature = signed(Resource::Stream);
+        let after_expiry = now() + chrono::Duration::seconds(i64::from(URL_TTL_SECS) + 1);
+
+        assert!(!verify_with_secret(
+            SECRET,
+            VIDEO_ID,
+            Resource::Stream,
+            &signature,
+            after_expiry
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_fields() {
+        let extended = StreamSignature {
+            expires: signed(Resource::Stream).expires + 3600,
+            ..signed(Resource::Stream)
+        };
+        assert!(!verifies(VIDEO_ID, Resource::Stream, &extended));
+
+        let other_user = StreamSignature {
+            user: USER_ID + 1,
+            ..signed(Resource::Stream)
+        };
+        assert!(!verifies(VIDEO_ID, Resource::Stream, &other_user));
+
+        let mut forged = signed(Resource::Stream);
+        let last = forged.signature.pop().unwrap();
+        forged.signature.push(if last == '0' { '1' } else { '0' });
+        assert!(!verifies(VIDEO_ID, Resource::Stream, &forged));
+
+        let not_hex = StreamSignature {
+            signature: "not hex".to_string(),
+            ..signed(Resource::Stream)
+        };
+        assert!(!verifies(VIDEO_ID, Resource::Stream, &not_hex));
+    }
+
+    #[test]
+    fn binds_signatures_to_the_video_and_resource() {
+        assert!(!verifies(
+            VIDEO_ID + 1,
+            Resource::Stream,
+            &signed(Resource::Stream)
+        ));
+        assert!(!verifies(
+            VIDEO_ID,
+            Resource::Stream,
+            &signed(Resource::Hls)
+        ));
+        assert!(!verifies(
+            VIDEO_ID,
+            Resource::Hls,
+            &signed(Resource::Stream)
+        ));
+    }
+
+    #[test]
+    fn rejects_signatures_made_with_another_secret() {
+        let expires_at = now() + chrono::Duration::seconds(URL_TTL_SECS.into());
+        let signature =
+            StreamSignature::with_secret("other", VIDEO_ID, Resource::Stream, USER_ID, expires_at);
+
+        assert!(!verifies(VIDEO_ID, Resource::Stream, &signature));
+    }
+}
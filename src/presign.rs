@@ -0,0 +1,84 @@
+//! AWS Signature Version 4 query string signing, for the requests `rust-s3`
+//! is unable to presign itself. See
+//! https://docs.aws.amazon.com/AmazonS3/latest/API/sigv4-query-string-auth.html
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// Presigned URL for uploading one part of a multipart upload. Assumes a
+/// path style bucket, like the one we create in `main`.
+pub async fn upload_part_url(
+    bucket: &s3::Bucket,
+    key: &str,
+    upload_id: &str,
+    part_number: u32,
+    expiry_secs: u32,
+) -> Result<String, s3::error::S3Error> {
+    let access_key = bucket.access_key().await?.unwrap_or_default();
+    let secret_key = bucket.secret_key().await?.unwrap_or_default();
+    let region = bucket.region().to_string();
+    let host = bucket.host();
+
+    let now = chrono::Utc::now();
+    let date = now.format("%Y%m%d").to_string();
+    let datetime = now.format("%Y%m%dT%H%M%SZ").to_string();
+
+    let scope = format!("{date}/{region}/s3/aws4_request");
+    let path = format!("/{}/{}", uri_encode(&bucket.name()), uri_encode(key));
+
+    // Must be sorted by key
+    let mut query = [
+        ("X-Amz-Algorithm", "AWS4-HMAC-SHA256".to_string()),
+        ("X-Amz-Credential", format!("{access_key}/{scope}")),
+        ("X-Amz-Date", datetime.clone()),
+        ("X-Amz-Expires", expiry_secs.to_string()),
+        ("X-Amz-SignedHeaders", "host".to_string()),
+        ("partNumber", part_number.to_string()),
+        ("uploadId", upload_id.to_string()),
+    ];
+    query.sort();
+
+    let query = query
+        .iter()
+        .map(|(key, value)| format!("{}={}", uri_encode(key), uri_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let canonical_request = format!("PUT\n{path}\n{query}\nhost:{host}\n\nhost\nUNSIGNED-PAYLOAD");
+
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{datetime}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let signing_key = [region.as_str(), "s3", "aws4_request"].iter().fold(
+        hmac(format!("AWS4{secret_key}").as_bytes(), &date),
+        |key, part| hmac(&key, part),
+    );
+
+    let signature = hex::encode(hmac(&signing_key, &string_to_sign));
+
+    Ok(format!(
+        "{}://{host}{path}?{query}&X-Amz-Signature={signature}",
+        bucket.scheme()
+    ))
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode everything but the unreserved characters of RFC 3986
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
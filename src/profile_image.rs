@@ -0,0 +1,126 @@
+//! Avatars and banners of users. Like thumbnails, each image is stored in
+//! every size and an upload gets a new id instead of overwriting the current
+//! one.
+
+use crate::storage::{Storage, StorageError};
+use crate::thumbnail::{self, ThumbnailSize};
+
+use ffmpeg::frame;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    Avatar,
+    Banner,
+}
+
+const AVATAR_SIZES: &[ThumbnailSize] = &[
+    ThumbnailSize {
+        name: "small",
+        width: 88,
+        height: 88,
+    },
+    ThumbnailSize {
+        name: "medium",
+        width: 176,
+        height: 176,
+    },
+    ThumbnailSize {
+        name: "large",
+        width: 800,
+        height: 800,
+    },
+];
+
+const BANNER_SIZES: &[ThumbnailSize] = &[
+    ThumbnailSize {
+        name: "small",
+        width: 640,
+        height: 360,
+    },
+    ThumbnailSize {
+        name: "medium",
+        width: 1280,
+        height: 720,
+    },
+    ThumbnailSize {
+        name: "large",
+        width: 2560,
+        height: 1440,
+    },
+];
+
+impl ImageKind {
+    pub const ALL: [ImageKind; 2] = [ImageKind::Avatar, ImageKind::Banner];
+
+    /// Top level directory of the images of this kind, also used in their URL
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ImageKind::Avatar => "avatars",
+            ImageKind::Banner => "banners",
+        }
+    }
+
+    pub fn from_prefix(prefix: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.prefix() == prefix)
+    }
+
+    pub fn sizes(self) -> &'static [ThumbnailSize] {
+        match self {
+            ImageKind::Avatar => AVATAR_SIZES,
+            ImageKind::Banner => BANNER_SIZES,
+        }
+    }
+
+    pub fn find_size(self, name: &str) -> Option<&'static ThumbnailSize> {
+        self.sizes().iter().find(|size| size.name == name)
+    }
+}
+
+pub fn key(kind: ImageKind, image: uuid::Uuid, size: &ThumbnailSize) -> String {
+    format!("{}/{image}/{}.jpg", kind.prefix(), size.name)
+}
+
+pub fn url(kind: ImageKind, image: uuid::Uuid, size: &ThumbnailSize) -> String {
+    format!("/images/{}/{image}/{}", kind.prefix(), size.name)
+}
+
+/// Encode `frame` as a JPEG in every size of `kind`
+pub fn encode_all(
+    frame: &frame::Video,
+    kind: ImageKind,
+) -> Result<Vec<(&'static ThumbnailSize, Vec<u8>)>, ffmpeg::Error> {
+    thumbnail::encode_sizes(frame, kind.sizes())
+}
+
+/// Store freshly encoded images, returning the id of the new image
+pub async fn upload(
+    storage: &dyn Storage,
+    kind: ImageKind,
+    images: Vec<(&'static ThumbnailSize, Vec<u8>)>,
+) -> Result<uuid::Uuid, StorageError> {
+    let image = uuid::Uuid::new_v4();
+
+    for (size, data) in images {
+        storage
+            .put_stream(
+                key(kind, image, size).as_str(),
+                &mut data.as_slice(),
+                thumbnail::CONTENT_TYPE,
+            )
+            .await?;
+    }
+
+    Ok(image)
+}
+
+pub async fn delete(
+    storage: &dyn Storage,
+    kind: ImageKind,
+    image: uuid::Uuid,
+) -> Result<(), StorageError> {
+    for size in kind.sizes() {
+        storage.delete(&key(kind, image, size)).await?;
+    }
+
+    Ok(())
+}
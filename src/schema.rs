@@ -1,5 +1,68 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "job_state"))]
+    pub struct JobState;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "playlist_kind"))]
+    pub struct PlaylistKind;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "video_status"))]
+    pub struct VideoStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "visibility"))]
+    pub struct Visibility;
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::*;
+
+    comment_likes (user_id, comment_id) {
+        user_id -> Int4,
+        comment_id -> Int4,
+        is_liking -> Bool,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::*;
+
+    comments (id) {
+        id -> Int4,
+        video_id -> Int4,
+        author_id -> Int4,
+        parent_id -> Nullable<Int4>,
+        content -> Text,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::*;
+    use super::sql_types::JobState;
+
+    jobs (id) {
+        id -> Int4,
+        video_id -> Int4,
+        state -> JobState,
+        attempts -> Int4,
+        max_attempts -> Int4,
+        last_error -> Nullable<Text>,
+        run_at -> Timestamptz,
+        locked_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use diesel_full_text_search::*;
@@ -8,6 +71,85 @@ diesel::table! {
         user_id -> Int4,
         video_id -> Int4,
         is_liking -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::*;
+    use super::sql_types::Visibility;
+
+    multipart_uploads (id) {
+        id -> Uuid,
+        user_id -> Int4,
+        s3_upload_id -> Varchar,
+        title -> Varchar,
+        description -> Text,
+        size -> Int8,
+        part_size -> Int8,
+        created_at -> Timestamptz,
+        visibility -> Visibility,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::*;
+
+    playlist_items (playlist_id, video_id) {
+        playlist_id -> Int4,
+        video_id -> Int4,
+        position -> Int4,
+        added_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::*;
+    use super::sql_types::PlaylistKind;
+    use super::sql_types::Visibility;
+
+    playlists (id) {
+        id -> Int4,
+        owner_id -> Int4,
+        kind -> PlaylistKind,
+        title -> Varchar,
+        description -> Text,
+        visibility -> Visibility,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::*;
+
+    subscriptions (subscriber_id, channel_id) {
+        subscriber_id -> Int4,
+        channel_id -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::*;
+    use super::sql_types::Visibility;
+
+    uploads (id) {
+        id -> Uuid,
+        user_id -> Int4,
+        title -> Varchar,
+        description -> Text,
+        upload_length -> Int8,
+        upload_offset -> Int8,
+        video_id -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        visibility -> Visibility,
     }
 }
 
@@ -19,6 +161,13 @@ diesel::table! {
         id -> Int4,
         username -> Varchar,
         password -> Varchar,
+        subscriber_count -> Int8,
+        display_name -> Nullable<Varchar>,
+        bio -> Text,
+        links -> Array<Nullable<Text>>,
+        avatar -> Nullable<Uuid>,
+        banner -> Nullable<Uuid>,
+        history_paused -> Bool,
     }
 }
 
@@ -26,6 +175,39 @@ diesel::table! {
     use diesel::sql_types::*;
     use diesel_full_text_search::*;
 
+    video_metadata (video_id) {
+        video_id -> Int4,
+        container -> Varchar,
+        width -> Int4,
+        height -> Int4,
+        frame_rate -> Nullable<Float8>,
+        video_codec -> Varchar,
+        audio_codec -> Nullable<Varchar>,
+        audio_channel_layout -> Nullable<Varchar>,
+        bit_rate -> Nullable<Int8>,
+        rotation -> Int4,
+        has_captions -> Bool,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::*;
+
+    video_stats (video_id) {
+        video_id -> Int4,
+        like_count -> Int8,
+        dislike_count -> Int8,
+        view_count -> Int8,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::*;
+    use super::sql_types::VideoStatus;
+    use super::sql_types::Visibility;
+
     videos (id) {
         id -> Int4,
         title -> Varchar,
@@ -35,15 +217,61 @@ diesel::table! {
         published_at -> Timestamptz,
         author_id -> Int4,
         textsearchable_index_col -> Tsvector,
+        master_playlist -> Nullable<Varchar>,
+        status -> VideoStatus,
+        thumbnail -> Nullable<Uuid>,
+        visibility -> Visibility,
+        updated_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
+        comments_enabled -> Bool,
+        pinned_comment_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::*;
+
+    watch_history (user_id, video_id) {
+        user_id -> Int4,
+        video_id -> Int4,
+        position_seconds -> Int8,
+        watched_at -> Timestamptz,
     }
 }
 
+diesel::joinable!(comment_likes -> comments (comment_id));
+diesel::joinable!(comment_likes -> users (user_id));
+diesel::joinable!(comments -> users (author_id));
+diesel::joinable!(comments -> videos (video_id));
+diesel::joinable!(jobs -> videos (video_id));
 diesel::joinable!(likes -> users (user_id));
 diesel::joinable!(likes -> videos (video_id));
+diesel::joinable!(multipart_uploads -> users (user_id));
+diesel::joinable!(playlist_items -> playlists (playlist_id));
+diesel::joinable!(playlist_items -> videos (video_id));
+diesel::joinable!(playlists -> users (owner_id));
+diesel::joinable!(uploads -> users (user_id));
+diesel::joinable!(uploads -> videos (video_id));
+diesel::joinable!(video_metadata -> videos (video_id));
+diesel::joinable!(video_stats -> videos (video_id));
 diesel::joinable!(videos -> users (author_id));
+diesel::joinable!(watch_history -> users (user_id));
+diesel::joinable!(watch_history -> videos (video_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    comment_likes,
+    comments,
+    jobs,
     likes,
+    multipart_uploads,
+    playlist_items,
+    playlists,
+    subscriptions,
+    uploads,
     users,
+    video_metadata,
+    video_stats,
     videos,
+    watch_history,
 );
@@ -0,0 +1,357 @@
+use super::{ObjectInfo, ObjectSummary, Storage, StorageError};
+
+use std::path::{Path, PathBuf};
+
+use axum::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+/// Objects being written, moved in place once complete. Like every name
+/// starting with a dot, it is not a valid key.
+const TMP_DIR: &str = ".tmp";
+
+/// Objects stored as files in a directory, so that the server runs without an
+/// object store. The key is the path of the file below `root`.
+#[derive(Debug)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub async fn open(root: &Path) -> std::io::Result<Self> {
+        tokio::fs::create_dir_all(root.join(TMP_DIR)).await?;
+
+        Ok(Self {
+            root: root.to_owned(),
+        })
+    }
+
+    /// Never let a key escape the root
+    fn path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        let valid = key
+            .split('/')
+            .all(|segment| !segment.is_empty() && !segment.starts_with('.'));
+
+        if !valid {
+            return Err(StorageError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid key '{key}'"),
+            )));
+        }
+
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put_stream(
+        &self,
+        key: &str,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+        _content_type: &str,
+    ) -> Result<(), StorageError> {
+        let path = self.path(key)?;
+
+        let tmp_path = self
+            .root
+            .join(TMP_DIR)
+            .join(uuid::Uuid::new_v4().to_string());
+
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+
+        let written = async {
+            tokio::io::copy(reader, &mut file).await?;
+            file.flush().await?;
+
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            tokio::fs::rename(&tmp_path, &path).await
+        }
+        .await;
+
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+        }
+
+        Ok(written?)
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError> {
+        let metadata = tokio::fs::metadata(self.path(key)?).await?;
+
+        if !metadata.is_file() {
+            return Err(StorageError::NotFound);
+        }
+
+        let modified = chrono::DateTime::<chrono::Utc>::from(metadata.modified()?);
+
+        Ok(ObjectInfo {
+            size: metadata.len(),
+            content_type: None,
+            etag: Some(format!(
+                "\"{:x}-{:x}\"",
+                metadata.len(),
+                modified.timestamp_nanos_opt().unwrap_or_default()
+            )),
+            last_modified: Some(modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string()),
+        })
+    }
+
+    async fn get_range(
+        &self,
+        key: &str,
+        start: u64,
+        end: Option<u64>,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<(), StorageError> {
+        let mut file = tokio::fs::File::open(self.path(key)?).await?;
+        file.seek(std::io::SeekFrom::Start(start)).await?;
+
+        match end {
+            Some(end) => {
+                let mut range = file.take(end.saturating_sub(start) + 1);
+                tokio::io::copy(&mut range, writer).await?;
+            }
+            None => {
+                tokio::io::copy(&mut file, writer).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let path = self.path(key)?;
+
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+
+        // Don't leave empty directories behind, removal fails on the first one
+        // that isn't
+        for dir in path.ancestors().skip(1) {
+            if dir == self.root || tokio::fs::remove_dir(dir).await.is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectSummary>, StorageError> {
+        let mut objects = Vec::new();
+        let mut pending = vec![self.root.clone()];
+
+        while let Some(current) = pending.pop() {
+            let mut entries = tokio::fs::read_dir(&current).await?;
+
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+
+                let path = entry.path();
+                let metadata = entry.metadata().await?;
+
+                if metadata.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+
+                let key = path
+                    .strip_prefix(&self.root)
+                    .unwrap_or(&path)
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+
+                if !key.starts_with(prefix) {
+                    continue;
+                }
+
+                objects.push(ObjectSummary {
+                    key,
+                    size: metadata.len(),
+                    last_modified: metadata.modified().ok().map(chrono::DateTime::from),
+                });
+            }
+        }
+
+        Ok(objects)
+    }
+
+    async fn presign_get(&self, _key: &str, _expiry_secs: u32) -> Result<String, StorageError> {
+        Err(StorageError::Unsupported("presigned URLs"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn open() -> (tempfile::TempDir, LocalStorage) {
+        let root = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::open(root.path()).await.unwrap();
+
+        (root, storage)
+    }
+
+    async fn put(storage: &LocalStorage, key: &str, data: &[u8]) -> Result<(), StorageError> {
+        storage
+            .put_stream(key, &mut &data[..], "application/octet-stream")
+            .await
+    }
+
+    #[tokio::test]
+    async fn stores_and_reads_back_objects() {
+        let (_root, storage) = open().await;
+
+        put(&storage, "bucket/hls/720p/index.m3u8", b"#EXTM3U")
+            .await
+            .unwrap();
+        // Replacing an object is allowed
+        put(&storage, "bucket/original", b"first").await.unwrap();
+        put(&storage, "bucket/original", b"second").await.unwrap();
+
+        assert_eq!(
+            storage.get("bucket/hls/720p/index.m3u8").await.unwrap(),
+            b"#EXTM3U"
+        );
+        assert_eq!(storage.get("bucket/original").await.unwrap(), b"second");
+
+        let info = storage.head("bucket/original").await.unwrap();
+        assert_eq!(info.size, 6);
+        assert!(info.etag.is_some());
+        assert!(info.last_modified.is_some());
+    }
+
+    #[tokio::test]
+    async fn reads_ranges() {
+        let (_root, storage) = open().await;
+        put(&storage, "object", b"0123456789").await.unwrap();
+
+        let mut data = Vec::new();
+        storage
+            .get_range("object", 3, Some(6), &mut data)
+            .await
+            .unwrap();
+        assert_eq!(data, b"3456");
+
+        let mut data = Vec::new();
+        storage
+            .get_range("object", 7, None, &mut data)
+            .await
+            .unwrap();
+        assert_eq!(data, b"789");
+    }
+
+    #[tokio::test]
+    async fn reports_missing_objects() {
+        let (_root, storage) = open().await;
+        put(&storage, "bucket/original", b"data").await.unwrap();
+
+        assert!(matches!(
+            storage.head("missing").await,
+            Err(StorageError::NotFound)
+        ));
+        assert!(matches!(
+            storage.get("missing").await,
+            Err(StorageError::NotFound)
+        ));
+        // A directory isn't an object
+        assert!(matches!(
+            storage.head("bucket").await,
+            Err(StorageError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn keeps_keys_inside_the_root() {
+        let (root, storage) = open().await;
+
+        // Something that must stay out of reach, next to the root
+        let outside = root.path().join("..").join("outside");
+
+        for key in [
+            "../outside",
+            "bucket/../../outside",
+            "/etc/passwd",
+            "bucket//original",
+            "",
+            ".tmp/object",
+            "bucket/.hidden",
+        ] {
+            assert!(put(&storage, key, b"data").await.is_err(), "{key}");
+            assert!(storage.head(key).await.is_err(), "{key}");
+            assert!(storage.delete(key).await.is_err(), "{key}");
+        }
+
+        assert!(!outside.exists());
+    }
+
+    #[tokio::test]
+    async fn deletes_objects_and_their_empty_directories() {
+        let (root, storage) = open().await;
+        put(&storage, "bucket/hls/240p/segment_0000.ts", b"ts")
+            .await
+            .unwrap();
+        put(&storage, "bucket/original", b"data").await.unwrap();
+
+        storage
+            .delete("bucket/hls/240p/segment_0000.ts")
+            .await
+            .unwrap();
+
+        assert!(!root.path().join("bucket/hls").exists());
+        assert!(root.path().join("bucket/original").exists());
+
+        // Deleting twice is fine
+        storage
+            .delete("bucket/hls/240p/segment_0000.ts")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn lists_objects_by_prefix() {
+        let (_root, storage) = open().await;
+        put(&storage, "a/original", b"1").await.unwrap();
+        put(&storage, "a/hls/master.m3u8", b"12").await.unwrap();
+        put(&storage, "b/original", b"123").await.unwrap();
+
+        let mut keys = storage
+            .list("a/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|object| (object.key, object.size))
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        assert_eq!(
+            keys,
+            [
+                ("a/hls/master.m3u8".to_string(), 2),
+                ("a/original".to_string(), 1)
+            ]
+        );
+
+        // Objects being written are never listed
+        assert_eq!(storage.list("").await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn cannot_presign() {
+        let (_root, storage) = open().await;
+
+        assert!(matches!(
+            storage.presign_get("object", 60).await,
+            Err(StorageError::Unsupported(_))
+        ));
+    }
+}
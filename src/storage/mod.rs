@@ -0,0 +1,172 @@
+//! Where originals, renditions and thumbnails are kept. Keys are `/`
+//! separated paths, see `thumbnail::key` and the worker for the layout.
+
+pub mod local;
+pub mod s3;
+
+use crate::config;
+
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::http::StatusCode;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+#[derive(Debug)]
+pub enum StorageError {
+    NotFound,
+    /// The backend has no way of doing this, e.g. presigning on local storage
+    Unsupported(&'static str),
+    S3(::s3::error::S3Error),
+    Io(std::io::Error),
+}
+
+impl StorageError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+            Self::S3(_) | Self::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "Object not found"),
+            Self::Unsupported(what) => write!(f, "The storage backend does not support {what}"),
+            Self::S3(err) => write!(f, "S3: {err}"),
+            Self::Io(err) => write!(f, "io: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<::s3::error::S3Error> for StorageError {
+    fn from(err: ::s3::error::S3Error) -> Self {
+        match err {
+            ::s3::error::S3Error::HttpFailWithBody(404, _) => Self::NotFound,
+            err => Self::S3(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFound,
+            _ => Self::Io(err),
+        }
+    }
+}
+
+/// What `Storage::head` tells about an object, in the form HTTP wants it
+#[derive(Debug)]
+pub struct ObjectInfo {
+    pub size: u64,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    /// HTTP date
+    pub last_modified: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ObjectSummary {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A part of a multipart upload, as uploaded by the client
+#[derive(Debug)]
+pub struct Part {
+    pub part_number: u32,
+    pub etag: String,
+}
+
+#[async_trait]
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    /// Store everything `reader` yields at `key`, replacing any previous object
+    async fn put_stream(
+        &self,
+        key: &str,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+        content_type: &str,
+    ) -> Result<(), StorageError>;
+
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError>;
+
+    /// Write the object from byte `start` to `end`, both inclusive, or to the
+    /// end of the object
+    async fn get_range(
+        &self,
+        key: &str,
+        start: u64,
+        end: Option<u64>,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<(), StorageError>;
+
+    /// Deleting a missing object is not an error
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    /// Every object whose key starts with `prefix`
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectSummary>, StorageError>;
+
+    /// URL anyone can download the object from, until it expires
+    async fn presign_get(&self, key: &str, expiry_secs: u32) -> Result<String, StorageError>;
+
+    /// Start a multipart upload the client sends straight to the backend.
+    /// Returns the id of the upload.
+    async fn create_multipart_upload(&self, _key: &str) -> Result<String, StorageError> {
+        Err(StorageError::Unsupported("multipart uploads"))
+    }
+
+    async fn presign_upload_part(
+        &self,
+        _key: &str,
+        _upload_id: &str,
+        _part_number: u32,
+        _expiry_secs: u32,
+    ) -> Result<String, StorageError> {
+        Err(StorageError::Unsupported("multipart uploads"))
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        _key: &str,
+        _upload_id: &str,
+        _parts: Vec<Part>,
+    ) -> Result<(), StorageError> {
+        Err(StorageError::Unsupported("multipart uploads"))
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        _key: &str,
+        _upload_id: &str,
+    ) -> Result<(), StorageError> {
+        Err(StorageError::Unsupported("multipart uploads"))
+    }
+
+    /// The whole object in memory, for small ones like thumbnails
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        let mut data = Vec::new();
+        self.get_range(key, 0, None, &mut data).await?;
+
+        Ok(data)
+    }
+}
+
+/// The backend selected in the configuration
+pub async fn from_config() -> Arc<dyn Storage> {
+    match config::config().await.storage() {
+        config::StorageConfig::S3(s3_config) => Arc::new(s3::S3Storage::connect(s3_config).await),
+        config::StorageConfig::Local { dir } => Arc::new(
+            local::LocalStorage::open(dir)
+                .await
+                .expect("cannot open local storage"),
+        ),
+    }
+}
@@ -0,0 +1,174 @@
+use super::{ObjectInfo, ObjectSummary, Part, Storage, StorageError};
+use crate::{config, presign};
+
+use axum::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Any S3 compatible object store, addressed path style
+#[derive(Debug)]
+pub struct S3Storage {
+    bucket: ::s3::Bucket,
+}
+
+impl S3Storage {
+    /// Connect to the configured bucket, creating it if needed
+    pub async fn connect(config: &config::S3Config) -> Self {
+        let region = ::s3::Region::Custom {
+            region: "eu-central-1".to_owned(),
+            endpoint: config.base_url().to_owned(),
+        };
+
+        let credentials = ::s3::creds::Credentials::new(
+            Some(config.access_key()),
+            Some(config.secret_key()),
+            None,
+            None,
+            None,
+        )
+        .expect("invalid S3 credentials");
+
+        let mut bucket = ::s3::Bucket::new(config.bucket(), region.clone(), credentials.clone())
+            .expect("cannot access S3 bucket")
+            .with_path_style();
+
+        if !bucket
+            .exists()
+            .await
+            .expect("cannot check if bucket exists")
+        {
+            tracing::info!("bucket {} does not exist, creating it...", config.bucket());
+
+            bucket = ::s3::Bucket::create_with_path_style(
+                config.bucket(),
+                region,
+                credentials,
+                ::s3::BucketConfiguration::default(),
+            )
+            .await
+            .expect("cannot create bucket")
+            .bucket;
+        }
+
+        Self { bucket }
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put_stream(
+        &self,
+        key: &str,
+        mut reader: &mut (dyn AsyncRead + Unpin + Send),
+        content_type: &str,
+    ) -> Result<(), StorageError> {
+        self.bucket
+            .put_object_stream_with_content_type(&mut reader, key, content_type)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError> {
+        let (head, status) = self.bucket.head_object(key).await?;
+
+        if status == 404 {
+            return Err(StorageError::NotFound);
+        }
+
+        Ok(ObjectInfo {
+            size: head.content_length.unwrap_or(0).max(0) as u64,
+            content_type: head.content_type,
+            etag: head.e_tag,
+            last_modified: head.last_modified,
+        })
+    }
+
+    async fn get_range(
+        &self,
+        key: &str,
+        start: u64,
+        end: Option<u64>,
+        mut writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<(), StorageError> {
+        self.bucket
+            .get_object_range_to_writer(key, start, end, &mut writer)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.bucket.delete_object(key).await?;
+
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectSummary>, StorageError> {
+        let listing = self.bucket.list(prefix.to_owned(), None).await?;
+
+        Ok(listing
+            .into_iter()
+            .flat_map(|result| result.contents)
+            .map(|object| ObjectSummary {
+                last_modified: chrono::DateTime::parse_from_rfc3339(&object.last_modified)
+                    .ok()
+                    .map(|last_modified| last_modified.to_utc()),
+                size: object.size,
+                key: object.key,
+            })
+            .collect())
+    }
+
+    async fn presign_get(&self, key: &str, expiry_secs: u32) -> Result<String, StorageError> {
+        Ok(self.bucket.presign_get(key, expiry_secs, None).await?)
+    }
+
+    async fn create_multipart_upload(&self, key: &str) -> Result<String, StorageError> {
+        let multipart = self
+            .bucket
+            .initiate_multipart_upload(key, "application/octet-stream")
+            .await?;
+
+        Ok(multipart.upload_id)
+    }
+
+    async fn presign_upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        expiry_secs: u32,
+    ) -> Result<String, StorageError> {
+        Ok(
+            presign::upload_part_url(&self.bucket, key, upload_id, part_number, expiry_secs)
+                .await?,
+        )
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<Part>,
+    ) -> Result<(), StorageError> {
+        let parts = parts
+            .into_iter()
+            .map(|part| ::s3::serde_types::Part {
+                part_number: part.part_number,
+                etag: part.etag,
+            })
+            .collect();
+
+        self.bucket
+            .complete_multipart_upload(key, upload_id, parts)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), StorageError> {
+        self.bucket.abort_upload(key, upload_id).await?;
+
+        Ok(())
+    }
+}
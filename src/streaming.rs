@@ -0,0 +1,429 @@
+//! Serving stored objects through the API with support for byte ranges and
+//! conditional requests, so players can seek and browsers can cache
+
+use crate::errors;
+use crate::storage::Storage;
+
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::Response;
+
+/// Size of the in-memory pipe between the storage and the response body
+const PIPE_CAPACITY: usize = 256 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    /// First and last byte, both inclusive
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Respond with the object stored at `key`, or the part of it requested by
+/// the `Range` header
+pub async fn stream_object(
+    storage: &Arc<dyn Storage>,
+    key: &str,
+    request_headers: &HeaderMap,
+    fallback_content_type: &str,
+) -> Result<Response, (StatusCode, String)> {
+    let head = storage
+        .head(key)
+        .await
+        .map_err(|err| (err.status_code(), err.to_string()))?;
+
+    let length = head.size;
+
+    let content_type = head
+        .content_type
+        .filter(|content_type| content_type != "application/octet-stream")
+        .unwrap_or_else(|| fallback_content_type.to_owned());
+
+    let mut response = Response::builder()
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CONTENT_TYPE, content_type);
+
+    if let Some(etag) = &head.etag {
+        response = response.header(header::ETAG, etag);
+    }
+
+    if let Some(last_modified) = &head.last_modified {
+        response = response.header(header::LAST_MODIFIED, last_modified);
+    }
+
+    if let (Some(if_none_match), Some(etag)) = (
+        request_headers.get(header::IF_NONE_MATCH),
+        head.etag.as_deref(),
+    ) {
+        if etag_matches(if_none_match, etag) {
+            return response
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .map_err(errors::internal_error);
+        }
+    }
+
+    // `If-None-Match` takes precedence, dates are only compared without it
+    if let (None, Some(if_modified_since), Some(last_modified)) = (
+        request_headers.get(header::IF_NONE_MATCH),
+        request_headers.get(header::IF_MODIFIED_SINCE),
+        head.last_modified.as_deref(),
+    ) {
+        if !modified_since(if_modified_since, last_modified) {
+            return response
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .map_err(errors::internal_error);
+        }
+    }
+
+    // A range only applies to the representation the client already has
+    let range_applies = match request_headers.get(header::IF_RANGE) {
+        None => true,
+        Some(if_range) => {
+            head.etag.as_deref().is_some_and(|etag| {
+                !etag.starts_with("W/") && if_range.as_bytes() == etag.as_bytes()
+            }) || head
+                .last_modified
+                .as_deref()
+                .is_some_and(|last_modified| if_range.as_bytes() == last_modified.as_bytes())
+        }
+    };
+
+    let range = match request_headers
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok())
+    {
+        Some(range) if range_applies => parse_range(range, length),
+        _ => ByteRange::Full,
+    };
+
+    let (start, end) = match range {
+        ByteRange::Full => {
+            response = response.status(StatusCode::OK);
+            (0, length.checked_sub(1))
+        }
+        ByteRange::Partial(start, end) => {
+            response = response.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_RANGE,
+                format!("bytes {start}-{end}/{length}"),
+            );
+            (start, Some(end))
+        }
+        ByteRange::Unsatisfiable => {
+            return response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{length}"))
+                .body(Body::empty())
+                .map_err(errors::internal_error);
+        }
+    };
+
+    let Some(end) = end else {
+        // Nothing to send for an empty object
+        return response
+            .header(header::CONTENT_LENGTH, 0)
+            .body(Body::empty())
+            .map_err(errors::internal_error);
+    };
+
+    let (mut writer, reader) = tokio::io::duplex(PIPE_CAPACITY);
+
+    let storage = storage.clone();
+    let key = key.to_owned();
+
+    // The download runs until the client has read everything or went away
+    tokio::spawn(async move {
+        if let Err(err) = storage.get_range(&key, start, Some(end), &mut writer).await {
+            tracing::warn!(key, "cannot stream object: {err}");
+        }
+    });
+
+    response
+        .header(header::CONTENT_LENGTH, end - start + 1)
+        .body(Body::from_stream(tokio_util::io::ReaderStream::new(reader)))
+        .map_err(errors::internal_error)
+}
+
+/// Only single ranges are supported, anything else gets the whole object
+/// which is what RFC 9110 allows
+fn parse_range(value: &str, length: u64) -> ByteRange {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return ByteRange::Full,
+        // The last `suffix` bytes
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(suffix) => (length.saturating_sub(suffix), length.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => (start, length.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(length.saturating_sub(1))),
+            _ => return ByteRange::Full,
+        },
+    };
+
+    if length == 0 || start >= length {
+        return ByteRange::Unsatisfiable;
+    }
+
+    ByteRange::Partial(start, end)
+}
+
+/// Whether `last_modified` is later than `If-Modified-Since`, which it is
+/// when either date can't be parsed
+fn modified_since(if_modified_since: &HeaderValue, last_modified: &str) -> bool {
+    let parse = |date: &str| chrono::DateTime::parse_from_rfc2822(date).ok();
+
+    let Some(since) = if_modified_since.to_str().ok().and_then(parse) else {
+        return true;
+    };
+
+    parse(last_modified).is_none_or(|last_modified| last_modified > since)
+}
+
+/// Weak comparison against an `If-None-Match` list
+fn etag_matches(if_none_match: &HeaderValue, etag: &str) -> bool {
+    let Ok(if_none_match) = if_none_match.to_str() else {
+        return false;
+    };
+
+    let etag = etag.trim_start_matches("W/");
+
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::local::LocalStorage;
+
+    #[test]
+    fn parses_closed_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0, 99));
+        assert_eq!(parse_range("bytes=10-10", 1000), ByteRange::Partial(10, 10));
+        // The end is clamped to the object
+        assert_eq!(
+            parse_range("bytes=900-2000", 1000),
+            ByteRange::Partial(900, 999)
+        );
+    }
+
+    #[test]
+    fn parses_open_and_suffix_ranges() {
+        assert_eq!(
+            parse_range("bytes=500-", 1000),
+            ByteRange::Partial(500, 999)
+        );
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            ByteRange::Partial(900, 999)
+        );
+        // A suffix longer than the object is the whole object
+        assert_eq!(parse_range("bytes=-5000", 1000), ByteRange::Partial(0, 999));
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(
+            parse_range("bytes=1500-2000", 1000),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_unsupported_or_invalid_ranges() {
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), ByteRange::Full);
+        assert_eq!(parse_range("items=0-1", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=9-5", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=a-b", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=-", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=5", 1000), ByteRange::Full);
+    }
+
+    #[test]
+    fn matches_etags_weakly() {
+        let etag = "\"abc\"";
+
+        assert!(etag_matches(&HeaderValue::from_static("\"abc\""), etag));
+        assert!(etag_matches(&HeaderValue::from_static("W/\"abc\""), etag));
+        assert!(etag_matches(
+            &HeaderValue::from_static("\"abc\""),
+            "W/\"abc\""
+        ));
+        assert!(etag_matches(
+            &HeaderValue::from_static("\"xyz\", W/\"abc\""),
+            etag
+        ));
+        assert!(etag_matches(&HeaderValue::from_static("*"), etag));
+        assert!(!etag_matches(&HeaderValue::from_static("\"xyz\""), etag));
+        assert!(!etag_matches(&HeaderValue::from_static("abc"), etag));
+    }
+
+    #[test]
+    fn compares_modification_dates() {
+        let last_modified = "Wed, 10 Jan 2024 10:00:00 GMT";
+
+        let since = |date: &'static str| HeaderValue::from_static(date);
+
+        assert!(!modified_since(&since(last_modified), last_modified));
+        assert!(!modified_since(
+            &since("Thu, 11 Jan 2024 10:00:00 GMT"),
+            last_modified
+        ));
+        assert!(modified_since(
+            &since("Tue, 09 Jan 2024 10:00:00 GMT"),
+            last_modified
+        ));
+        // Unparseable dates never prevent sending the object
+        assert!(modified_since(&since("yesterday"), last_modified));
+        assert!(modified_since(&since(last_modified), "yesterday"));
+    }
+
+    async fn storage_with(key: &str, data: &[u8]) -> (tempfile::TempDir, Arc<dyn Storage>) {
+        let root = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::open(root.path()).await.unwrap();
+
+        storage
+            .put_stream(key, &mut &data[..], "application/octet-stream")
+            .await
+            .unwrap();
+
+        (root, Arc::new(storage))
+    }
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    async fn body(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn streams_the_requested_range() {
+        let (_root, storage) = storage_with("video", b"0123456789").await;
+
+        let response = stream_object(
+            &storage,
+            "video",
+            &headers(&[(header::RANGE, "bytes=2-5")]),
+            "video/mp4",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-5/10");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "video/mp4");
+        assert_eq!(body(response).await, b"2345");
+    }
+
+    #[tokio::test]
+    async fn streams_everything_without_a_range() {
+        let (_root, storage) = storage_with("video", b"0123456789").await;
+
+        let response = stream_object(&storage, "video", &HeaderMap::new(), "video/mp4")
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+        assert_eq!(body(response).await, b"0123456789");
+    }
+
+    #[tokio::test]
+    async fn refuses_ranges_past_the_end() {
+        let (_root, storage) = storage_with("video", b"0123456789").await;
+
+        let response = stream_object(
+            &storage,
+            "video",
+            &headers(&[(header::RANGE, "bytes=20-")]),
+            "video/mp4",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+    }
+
+    #[tokio::test]
+    async fn answers_conditional_requests() {
+        let (_root, storage) = storage_with("video", b"0123456789").await;
+
+        let first = stream_object(&storage, "video", &HeaderMap::new(), "video/mp4")
+            .await
+            .unwrap();
+        let etag = first.headers()[header::ETAG].to_str().unwrap().to_owned();
+        let last_modified = first.headers()[header::LAST_MODIFIED]
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        let cached = stream_object(
+            &storage,
+            "video",
+            &headers(&[(header::IF_NONE_MATCH, &etag)]),
+            "video/mp4",
+        )
+        .await
+        .unwrap();
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+
+        let cached = stream_object(
+            &storage,
+            "video",
+            &headers(&[(header::IF_MODIFIED_SINCE, &last_modified)]),
+            "video/mp4",
+        )
+        .await
+        .unwrap();
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+
+        // A stale `If-Range` gets the whole object instead of the range
+        let stale = stream_object(
+            &storage,
+            "video",
+            &headers(&[
+                (header::RANGE, "bytes=0-1"),
+                (header::IF_RANGE, "\"other\""),
+            ]),
+            "video/mp4",
+        )
+        .await
+        .unwrap();
+        assert_eq!(stale.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::storage::{Storage, StorageError};
+
+use ffmpeg::{codec, encoder, format, frame, software, Packet};
+
+pub const CONTENT_TYPE: &str = "image/jpeg";
+
+/// Directory of the thumbnails below the bucket of their video
+pub const PREFIX: &str = "thumbnails";
+
+/// JPEG quantizer, lower is better looking and bigger
+const JPEG_QUALITY: i32 = 3;
+
+/// `FF_QP2LAMBDA`, the scale ffmpeg uses for `global_quality`
+const QP2LAMBDA: i32 = 118;
+
+/// A thumbnail is scaled to fit within this box, keeping its aspect ratio
+#[derive(Debug, Clone, Copy)]
+pub struct ThumbnailSize {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub const SIZES: &[ThumbnailSize] = &[
+    ThumbnailSize {
+        name: "small",
+        width: 320,
+        height: 180,
+    },
+    ThumbnailSize {
+        name: "medium",
+        width: 640,
+        height: 360,
+    },
+    ThumbnailSize {
+        name: "large",
+        width: 1280,
+        height: 720,
+    },
+];
+
+pub fn find_size(name: &str) -> Option<&'static ThumbnailSize> {
+    SIZES.iter().find(|size| size.name == name)
+}
+
+/// Storage key of one size of a thumbnail. Every replacement gets a new
+/// `thumbnail` id so that cached images are never stale.
+pub fn key(bucket: uuid::Uuid, thumbnail: uuid::Uuid, size: &ThumbnailSize) -> String {
+    format!("{bucket}/{PREFIX}/{thumbnail}/{}.jpg", size.name)
+}
+
+/// Encode `frame` as a JPEG in every size
+pub fn encode_all(
+    frame: &frame::Video,
+) -> Result<Vec<(&'static ThumbnailSize, Vec<u8>)>, ffmpeg::Error> {
+    encode_sizes(frame, SIZES)
+}
+
+/// Encode `frame` as a JPEG in each of `sizes`
+pub fn encode_sizes(
+    frame: &frame::Video,
+    sizes: &'static [ThumbnailSize],
+) -> Result<Vec<(&'static ThumbnailSize, Vec<u8>)>, ffmpeg::Error> {
+    sizes
+        .iter()
+        .map(|size| Ok((size, encode_jpeg(frame, size)?)))
+        .collect()
+}
+
+/// Store freshly encoded thumbnails next to the video, returning the id of
+/// the new thumbnail
+pub async fn upload(
+    storage: &dyn Storage,
+    bucket: uuid::Uuid,
+    images: Vec<(&'static ThumbnailSize, Vec<u8>)>,
+) -> Result<uuid::Uuid, StorageError> {
+    let thumbnail = uuid::Uuid::new_v4();
+
+    for (size, image) in images {
+        storage
+            .put_stream(
+                key(bucket, thumbnail, size).as_str(),
+                &mut image.as_slice(),
+                CONTENT_TYPE,
+            )
+            .await?;
+    }
+
+    Ok(thumbnail)
+}
+
+pub async fn delete(
+    storage: &dyn Storage,
+    bucket: uuid::Uuid,
+    thumbnail: uuid::Uuid,
+) -> Result<(), StorageError> {
+    for size in SIZES {
+        storage.delete(&key(bucket, thumbnail, size)).await?;
+    }
+
+    Ok(())
+}
+
+fn encode_jpeg(frame: &frame::Video, size: &ThumbnailSize) -> Result<Vec<u8>, ffmpeg::Error> {
+    let (width, height) = fit(frame.width(), frame.height(), size);
+
+    let mut scaler = software::scaling::Context::get(
+        frame.format(),
+        frame.width(),
+        frame.height(),
+        format::Pixel::YUVJ420P,
+        width,
+        height,
+        software::scaling::Flags::LANCZOS,
+    )?;
+
+    let mut scaled = frame::Video::empty();
+    scaler.run(frame, &mut scaled)?;
+
+    let codec = encoder::find(codec::Id::MJPEG).ok_or(ffmpeg::Error::EncoderNotFound)?;
+
+    let mut jpeg_encoder = codec::context::Context::new().encoder().video()?;
+
+    jpeg_encoder.set_width(width);
+    jpeg_encoder.set_height(height);
+    jpeg_encoder.set_format(format::Pixel::YUVJ420P);
+    jpeg_encoder.set_time_base((1, 25));
+    jpeg_encoder.set_flags(codec::Flags::QSCALE);
+    jpeg_encoder.set_global_quality(JPEG_QUALITY * QP2LAMBDA);
+
+    let mut jpeg_encoder = jpeg_encoder.open_as(codec)?;
+
+    jpeg_encoder.send_frame(&scaled)?;
+    jpeg_encoder.send_eof()?;
+
+    let mut packet = Packet::empty();
+    jpeg_encoder.receive_packet(&mut packet)?;
+
+    packet
+        .data()
+        .map(|data| data.to_vec())
+        .ok_or(ffmpeg::Error::InvalidData)
+}
+
+/// Largest even dimensions that fit within `size` without upscaling
+fn fit(width: u32, height: u32, size: &ThumbnailSize) -> (u32, u32) {
+    let scale = f64::min(
+        size.width as f64 / width as f64,
+        size.height as f64 / height as f64,
+    )
+    .min(1.0);
+
+    let even = |value: f64| ((value / 2.0).round() as u32 * 2).max(2);
+
+    (even(width as f64 * scale), even(height as f64 * scale))
+}
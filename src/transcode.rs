@@ -0,0 +1,739 @@
+use crate::video_util;
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use ffmpeg::{
+    codec, decoder, encoder, filter, format, frame, media, software, Dictionary, Packet, Rational,
+};
+
+/// A single quality level of the HLS ladder
+#[derive(Debug, Clone, Copy)]
+pub struct Rendition {
+    pub name: &'static str,
+    /// Short side of the frame, the width of portrait videos
+    pub height: u32,
+    /// Average bitrate
+    pub video_bitrate: usize,
+}
+
+impl Rendition {
+    /// Peak bitrate the encoder is capped at
+    pub fn max_video_bitrate(&self) -> usize {
+        self.video_bitrate * 3 / 2
+    }
+}
+
+pub const LADDER: [Rendition; 4] = [
+    Rendition {
+        name: "240p",
+        height: 240,
+        video_bitrate: 400_000,
+    },
+    Rendition {
+        name: "480p",
+        height: 480,
+        video_bitrate: 1_000_000,
+    },
+    Rendition {
+        name: "720p",
+        height: 720,
+        video_bitrate: 2_800_000,
+    },
+    Rendition {
+        name: "1080p",
+        height: 1080,
+        video_bitrate: 5_000_000,
+    },
+];
+
+pub const MASTER_PLAYLIST: &str = "master.m3u8";
+
+const RENDITION_PLAYLIST: &str = "index.m3u8";
+const SEGMENT_SECONDS: u32 = 6;
+const AUDIO_BITRATE: usize = 128_000;
+const AUDIO_SAMPLE_RATE: i32 = 48_000;
+
+/// Size of the rate control buffer, in seconds at the peak bitrate. x264
+/// only enforces the peak when a buffer size is set.
+const VBV_BUFFER_SECONDS: usize = 2;
+
+/// Profile of every rendition, with its `profile_idc` and constraint flags as
+/// written in the `CODECS` attribute of the master playlist
+const H264_PROFILE: &str = "high";
+const H264_PROFILE_IDC: u8 = 0x64;
+const H264_CONSTRAINT_FLAGS: u8 = 0x00;
+
+/// `level_idc` of each H.264 level with its limits: macroblocks per second,
+/// macroblocks per frame and bitrate in kbit/s for the High profile
+const H264_LEVELS: [(u8, u64, u64, u64); 16] = [
+    (10, 1_485, 99, 80),
+    (11, 3_000, 396, 240),
+    (12, 6_000, 396, 480),
+    (13, 11_880, 396, 960),
+    (20, 11_880, 396, 2_500),
+    (21, 19_800, 792, 5_000),
+    (22, 20_250, 1_620, 5_000),
+    (30, 40_500, 1_620, 12_500),
+    (31, 108_000, 3_600, 17_500),
+    (32, 216_000, 5_120, 25_000),
+    (40, 245_760, 8_192, 25_000),
+    (41, 245_760, 8_192, 62_500),
+    (42, 522_240, 8_704, 62_500),
+    (50, 589_824, 22_080, 168_750),
+    (51, 983_040, 36_864, 300_000),
+    (52, 2_073_600, 36_864, 300_000),
+];
+
+#[derive(Debug)]
+pub enum TranscodeError {
+    Ffmpeg(ffmpeg::Error),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for TranscodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ffmpeg(err) => write!(f, "ffmpeg error: {err}"),
+            Self::Io(err) => write!(f, "io error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for TranscodeError {}
+
+impl From<ffmpeg::Error> for TranscodeError {
+    fn from(err: ffmpeg::Error) -> Self {
+        Self::Ffmpeg(err)
+    }
+}
+
+impl From<std::io::Error> for TranscodeError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Transcode `input` into every rendition of the ladder that does not exceed
+/// the source resolution, writing one HLS playlist per rendition plus a master
+/// playlist into `output_dir`. Rotated sources are turned upright, since
+/// players ignore the rotation of HLS streams.
+pub fn transcode_hls<P, Q>(input: P, output_dir: Q) -> Result<Vec<Rendition>, TranscodeError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let output_dir = output_dir.as_ref();
+
+    let mut ictx = format::input(&input)?;
+
+    let video_stream = ictx
+        .streams()
+        .best(media::Type::Video)
+        .ok_or(ffmpeg::Error::StreamNotFound)?;
+    let video_index = video_stream.index();
+    let video_time_base = video_stream.time_base();
+    let frame_rate = match video_stream.avg_frame_rate() {
+        rate if rate.numerator() > 0 && rate.denominator() > 0 => rate,
+        _ => Rational(30, 1),
+    };
+    let rotation = video_util::rotation(&video_stream);
+    let mut video_decoder = codec::context::Context::from_parameters(video_stream.parameters())?
+        .decoder()
+        .video()?;
+
+    let mut upright = Upright::new(&video_decoder, video_time_base, rotation)?;
+
+    let source = if rotation % 180 == 90 {
+        Source {
+            format: video_decoder.format(),
+            width: video_decoder.height(),
+            height: video_decoder.width(),
+        }
+    } else {
+        Source {
+            format: video_decoder.format(),
+            width: video_decoder.width(),
+            height: video_decoder.height(),
+        }
+    };
+
+    let mut audio = match ictx.streams().best(media::Type::Audio) {
+        Some(stream) => Some(AudioTranscoder::new(&stream)?),
+        None => None,
+    };
+
+    let mut renditions: Vec<Rendition> = LADDER
+        .iter()
+        .filter(|rendition| rendition.height <= source.width.min(source.height))
+        .copied()
+        .collect();
+
+    // Always produce at least the lowest rung, even for tiny sources
+    if renditions.is_empty() {
+        renditions.push(LADDER[0]);
+    }
+
+    let mut outputs = renditions
+        .iter()
+        .map(|rendition| {
+            RenditionOutput::new(
+                *rendition,
+                output_dir,
+                &source,
+                video_time_base,
+                frame_rate,
+                audio.as_ref(),
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut decoded = frame::Video::empty();
+
+    for (stream, packet) in ictx.packets() {
+        if stream.index() == video_index {
+            video_decoder.send_packet(&packet)?;
+
+            while video_decoder.receive_frame(&mut decoded).is_ok() {
+                encode_frame(&decoded, upright.as_mut(), &mut outputs)?;
+            }
+        } else if let Some(audio) = audio.as_mut().filter(|a| a.stream_index == stream.index()) {
+            audio.decoder.send_packet(&packet)?;
+            audio.drain(&mut outputs)?;
+        }
+    }
+
+    // Flush decoders, filters and encoders
+    video_decoder.send_eof()?;
+    while video_decoder.receive_frame(&mut decoded).is_ok() {
+        encode_frame(&decoded, upright.as_mut(), &mut outputs)?;
+    }
+
+    if let Some(upright) = upright.as_mut() {
+        upright.flush(&mut outputs)?;
+    }
+
+    for output in outputs.iter_mut() {
+        output.encode_video(None)?;
+    }
+
+    if let Some(audio) = audio.as_mut() {
+        audio.decoder.send_eof()?;
+        audio.drain(&mut outputs)?;
+        audio.flush(&mut outputs)?;
+    }
+
+    for output in outputs.iter_mut() {
+        output.octx.write_trailer()?;
+    }
+
+    write_master_playlist(output_dir, &outputs, audio.is_some())?;
+
+    Ok(renditions)
+}
+
+/// Frames as they reach the renditions, after rotation
+struct Source {
+    format: format::Pixel,
+    width: u32,
+    height: u32,
+}
+
+/// Feed a decoded frame to every rendition, through `upright` if the source is rotated
+fn encode_frame(
+    frame: &frame::Video,
+    upright: Option<&mut Upright>,
+    outputs: &mut [RenditionOutput],
+) -> Result<(), ffmpeg::Error> {
+    match upright {
+        Some(upright) => upright.rotate(frame, outputs),
+        None => outputs
+            .iter_mut()
+            .try_for_each(|output| output.encode_video(Some(frame))),
+    }
+}
+
+/// Filter graph applying the rotation of the source to its frames
+struct Upright {
+    filter: filter::Graph,
+}
+
+impl Upright {
+    /// `None` when `rotation`, in degrees clockwise, leaves frames as they are
+    fn new(
+        decoder: &decoder::Video,
+        time_base: Rational,
+        rotation: i32,
+    ) -> Result<Option<Self>, ffmpeg::Error> {
+        // Same filters as the `-autorotate` of the ffmpeg CLI
+        let spec = match rotation {
+            90 => "transpose=clock",
+            180 => "hflip,vflip",
+            270 => "transpose=cclock",
+            _ => return Ok(None),
+        };
+
+        let mut filter = filter::Graph::new();
+
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}:pixel_aspect={}",
+            decoder.width(),
+            decoder.height(),
+            decoder
+                .format()
+                .descriptor()
+                .ok_or(ffmpeg::Error::InvalidData)?
+                .name(),
+            time_base,
+            decoder.aspect_ratio(),
+        );
+
+        filter.add(&filter::find("buffer").unwrap(), "in", &args)?;
+        filter.add(&filter::find("buffersink").unwrap(), "out", "")?;
+
+        // The renditions' scalers expect the decoder's pixel format
+        filter
+            .get("out")
+            .unwrap()
+            .set_pixel_format(decoder.format());
+
+        filter.output("in", 0)?.input("out", 0)?.parse(spec)?;
+        filter.validate()?;
+
+        Ok(Some(Self { filter }))
+    }
+
+    fn rotate(
+        &mut self,
+        frame: &frame::Video,
+        outputs: &mut [RenditionOutput],
+    ) -> Result<(), ffmpeg::Error> {
+        self.filter.get("in").unwrap().source().add(frame)?;
+        self.encode_filtered(outputs)
+    }
+
+    fn flush(&mut self, outputs: &mut [RenditionOutput]) -> Result<(), ffmpeg::Error> {
+        self.filter.get("in").unwrap().source().flush()?;
+        self.encode_filtered(outputs)
+    }
+
+    fn encode_filtered(&mut self, outputs: &mut [RenditionOutput]) -> Result<(), ffmpeg::Error> {
+        let mut rotated = frame::Video::empty();
+
+        while self
+            .filter
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut rotated)
+            .is_ok()
+        {
+            for output in outputs.iter_mut() {
+                output.encode_video(Some(&rotated))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct RenditionOutput {
+    rendition: Rendition,
+    width: u32,
+    height: u32,
+    /// `level_idc` the encoder was held to
+    level: u8,
+    octx: format::context::Output,
+    scaler: software::scaling::Context,
+    video_encoder: encoder::Video,
+    input_time_base: Rational,
+    video_time_base: Rational,
+    audio_time_base: Option<Rational>,
+}
+
+impl RenditionOutput {
+    fn new(
+        rendition: Rendition,
+        output_dir: &Path,
+        source: &Source,
+        input_time_base: Rational,
+        frame_rate: Rational,
+        audio: Option<&AudioTranscoder>,
+    ) -> Result<Self, TranscodeError> {
+        let rendition_dir = output_dir.join(rendition.name);
+        fs::create_dir_all(&rendition_dir)?;
+
+        // The rung sets the short side, and H.264 needs even dimensions
+        let (width, height) = if source.height > source.width {
+            (
+                rendition.height & !1,
+                (source.height * rendition.height / source.width.max(1)) & !1,
+            )
+        } else {
+            (
+                (source.width * rendition.height / source.height.max(1)) & !1,
+                rendition.height & !1,
+            )
+        };
+
+        let mut octx = format::output_as(&rendition_dir.join(RENDITION_PLAYLIST), "hls")?;
+
+        let codec = encoder::find(codec::Id::H264).ok_or(ffmpeg::Error::EncoderNotFound)?;
+        let mut video_stream = octx.add_stream(codec)?;
+        let mut video_encoder =
+            codec::context::Context::from_parameters(video_stream.parameters())?
+                .encoder()
+                .video()?;
+
+        video_encoder.set_width(width);
+        video_encoder.set_height(height);
+        video_encoder.set_format(format::Pixel::YUV420P);
+        video_encoder.set_time_base(input_time_base);
+        video_encoder.set_frame_rate(Some(frame_rate));
+        video_encoder.set_bit_rate(rendition.video_bitrate);
+        video_encoder.set_max_bit_rate(rendition.max_video_bitrate());
+        // One keyframe per segment so that every segment is independently decodable
+        video_encoder.set_gop(keyframe_interval(frame_rate));
+
+        let level = h264_level(width, height, frame_rate, rendition.max_video_bitrate());
+
+        let mut x264_opts = Dictionary::new();
+        x264_opts.set("preset", "veryfast");
+        x264_opts.set("x264-params", "scenecut=0");
+        // Fixed rather than picked by x264, so that the master playlist can
+        // tell players what they are
+        x264_opts.set("profile", H264_PROFILE);
+        x264_opts.set("level", &level.to_string());
+        x264_opts.set(
+            "bufsize",
+            &(rendition.max_video_bitrate() * VBV_BUFFER_SECONDS).to_string(),
+        );
+
+        let video_encoder = video_encoder.open_as_with(codec, x264_opts)?;
+        video_stream.set_parameters(&video_encoder);
+        video_stream.set_time_base(input_time_base);
+
+        if let Some(audio) = audio {
+            let mut audio_stream = octx.add_stream(audio.codec)?;
+            audio_stream.set_parameters(&audio.encoder);
+            audio_stream.set_time_base((1, AUDIO_SAMPLE_RATE));
+        }
+
+        let mut hls_opts = Dictionary::new();
+        hls_opts.set("hls_time", &SEGMENT_SECONDS.to_string());
+        hls_opts.set("hls_playlist_type", "vod");
+        hls_opts.set(
+            "hls_segment_filename",
+            &rendition_dir.join("segment_%04d.ts").to_string_lossy(),
+        );
+
+        octx.write_header_with(hls_opts)?;
+
+        let video_time_base = octx.stream(0).map(|s| s.time_base()).unwrap();
+        let audio_time_base = audio.and_then(|_| octx.stream(1)).map(|s| s.time_base());
+
+        let scaler = software::scaling::Context::get(
+            source.format,
+            source.width,
+            source.height,
+            format::Pixel::YUV420P,
+            width,
+            height,
+            software::scaling::Flags::BICUBIC,
+        )?;
+
+        Ok(Self {
+            rendition,
+            width,
+            height,
+            level,
+            octx,
+            scaler,
+            video_encoder,
+            input_time_base,
+            video_time_base,
+            audio_time_base,
+        })
+    }
+
+    /// Scale and encode a decoded frame, or flush the encoder when `frame` is `None`
+    fn encode_video(&mut self, frame: Option<&frame::Video>) -> Result<(), ffmpeg::Error> {
+        match frame {
+            Some(frame) => {
+                let mut scaled = frame::Video::empty();
+                self.scaler.run(frame, &mut scaled)?;
+                scaled.set_pts(frame.timestamp());
+                self.video_encoder.send_frame(&scaled)?;
+            }
+            None => self.video_encoder.send_eof()?,
+        }
+
+        let mut encoded = Packet::empty();
+        while self.video_encoder.receive_packet(&mut encoded).is_ok() {
+            encoded.set_stream(0);
+            encoded.rescale_ts(self.input_time_base, self.video_time_base);
+            encoded.write_interleaved(&mut self.octx)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_audio_packet(&mut self, packet: &Packet) -> Result<(), ffmpeg::Error> {
+        let Some(audio_time_base) = self.audio_time_base else {
+            return Ok(());
+        };
+
+        let mut packet = packet.clone();
+        packet.set_stream(1);
+        packet.rescale_ts((1, AUDIO_SAMPLE_RATE), audio_time_base);
+        packet.write_interleaved(&mut self.octx)
+    }
+}
+
+/// Audio is identical in every rendition, so it is encoded once and the
+/// resulting packets are muxed into each output.
+struct AudioTranscoder {
+    stream_index: usize,
+    decoder: decoder::Audio,
+    /// Layout of the decoded audio, guessed from the channel count when the
+    /// source doesn't declare one
+    channel_layout: ffmpeg::ChannelLayout,
+    filter: filter::Graph,
+    codec: ffmpeg::Codec,
+    encoder: encoder::Audio,
+}
+
+impl AudioTranscoder {
+    fn new(stream: &format::stream::Stream) -> Result<Self, ffmpeg::Error> {
+        let decoder = codec::context::Context::from_parameters(stream.parameters())?
+            .decoder()
+            .audio()?;
+
+        let codec = encoder::find(codec::Id::AAC).ok_or(ffmpeg::Error::EncoderNotFound)?;
+        let mut encoder = codec::context::Context::new().encoder().audio()?;
+
+        encoder.set_rate(AUDIO_SAMPLE_RATE);
+        encoder.set_channel_layout(ffmpeg::ChannelLayout::STEREO);
+        encoder.set_channels(ffmpeg::ChannelLayout::STEREO.channels());
+        encoder.set_format(format::Sample::F32(format::sample::Type::Planar));
+        encoder.set_bit_rate(AUDIO_BITRATE);
+        encoder.set_time_base((1, AUDIO_SAMPLE_RATE));
+
+        let encoder = encoder.open_as(codec)?;
+
+        // Common for PCM, abuffer refuses an empty layout
+        let channel_layout = match decoder.channel_layout() {
+            layout if layout.is_empty() => {
+                ffmpeg::ChannelLayout::default(i32::from(decoder.channels()))
+            }
+            layout => layout,
+        };
+
+        let mut filter = filter::Graph::new();
+
+        let args = format!(
+            "time_base={}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+            stream.time_base(),
+            decoder.rate(),
+            decoder.format().name(),
+            channel_layout.bits()
+        );
+
+        filter.add(&filter::find("abuffer").unwrap(), "in", &args)?;
+        filter.add(&filter::find("abuffersink").unwrap(), "out", "")?;
+
+        {
+            let mut out = filter.get("out").unwrap();
+            out.set_sample_format(encoder.format());
+            out.set_channel_layout(encoder.channel_layout());
+            out.set_sample_rate(encoder.rate());
+        }
+
+        // Resample to the encoder format and express timestamps in samples
+        let spec = format!("aresample={AUDIO_SAMPLE_RATE},asettb=1/{AUDIO_SAMPLE_RATE}");
+        filter.output("in", 0)?.input("out", 0)?.parse(&spec)?;
+        filter.validate()?;
+
+        filter
+            .get("out")
+            .unwrap()
+            .sink()
+            .set_frame_size(encoder.frame_size());
+
+        Ok(Self {
+            stream_index: stream.index(),
+            decoder,
+            channel_layout,
+            filter,
+            codec,
+            encoder,
+        })
+    }
+
+    /// Push every frame the decoder has ready through the filter and encoder
+    fn drain(&mut self, outputs: &mut [RenditionOutput]) -> Result<(), ffmpeg::Error> {
+        let mut decoded = frame::Audio::empty();
+
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            let timestamp = decoded.timestamp();
+            decoded.set_pts(timestamp);
+            // Frames must match the layout the filter was configured with
+            if decoded.channel_layout().is_empty() {
+                decoded.set_channel_layout(self.channel_layout);
+            }
+            self.filter.get("in").unwrap().source().add(&decoded)?;
+            self.encode_filtered(outputs)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self, outputs: &mut [RenditionOutput]) -> Result<(), ffmpeg::Error> {
+        self.filter.get("in").unwrap().source().flush()?;
+        self.encode_filtered(outputs)?;
+
+        self.encoder.send_eof()?;
+        self.write_encoded(outputs)
+    }
+
+    fn encode_filtered(&mut self, outputs: &mut [RenditionOutput]) -> Result<(), ffmpeg::Error> {
+        let mut filtered = frame::Audio::empty();
+
+        while self
+            .filter
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut filtered)
+            .is_ok()
+        {
+            self.encoder.send_frame(&filtered)?;
+            self.write_encoded(outputs)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_encoded(&mut self, outputs: &mut [RenditionOutput]) -> Result<(), ffmpeg::Error> {
+        let mut encoded = Packet::empty();
+
+        while self.encoder.receive_packet(&mut encoded).is_ok() {
+            for output in outputs.iter_mut() {
+                output.write_audio_packet(&encoded)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn keyframe_interval(frame_rate: Rational) -> u32 {
+    let fps = f64::from(frame_rate).round().max(1.0) as u32;
+
+    fps * SEGMENT_SECONDS
+}
+
+/// The lowest level whose limits allow `width`x`height` at `frame_rate` and
+/// `max_bit_rate` bits per second, the highest one if none does
+fn h264_level(width: u32, height: u32, frame_rate: Rational, max_bit_rate: usize) -> u8 {
+    let frame_macroblocks = u64::from(width.div_ceil(16)) * u64::from(height.div_ceil(16));
+    let macroblocks_per_second = (frame_macroblocks as f64 * f64::from(frame_rate)).ceil() as u64;
+    let kbit_rate = (max_bit_rate as u64).div_ceil(1000);
+
+    H264_LEVELS
+        .iter()
+        .find(
+            |(_, max_macroblocks_per_second, max_frame_macroblocks, max_kbit_rate)| {
+                macroblocks_per_second <= *max_macroblocks_per_second
+                    && frame_macroblocks <= *max_frame_macroblocks
+                    && kbit_rate <= *max_kbit_rate
+            },
+        )
+        .map_or(H264_LEVELS[H264_LEVELS.len() - 1].0, |(level, ..)| *level)
+}
+
+/// RFC 6381 name of a rendition's video codec, e.g. `avc1.64001f`
+fn h264_codec_string(level: u8) -> String {
+    format!("avc1.{H264_PROFILE_IDC:02x}{H264_CONSTRAINT_FLAGS:02x}{level:02x}")
+}
+
+fn write_master_playlist(
+    output_dir: &Path,
+    outputs: &[RenditionOutput],
+    has_audio: bool,
+) -> Result<(), std::io::Error> {
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+
+    for output in outputs {
+        let audio_bitrate = if has_audio { AUDIO_BITRATE } else { 0 };
+        // BANDWIDTH is the peak, see RFC 8216 section 4.3.4.2
+        let bandwidth = output.rendition.max_video_bitrate() + audio_bitrate;
+        let average_bandwidth = output.rendition.video_bitrate + audio_bitrate;
+        let video_codec = h264_codec_string(output.level);
+        let codecs = if has_audio {
+            format!("{video_codec},mp4a.40.2")
+        } else {
+            video_codec
+        };
+
+        // Writing into a `String` cannot fail
+        let _ = writeln!(
+            playlist,
+            "#EXT-X-STREAM-INF:BANDWIDTH={bandwidth},AVERAGE-BANDWIDTH={average_bandwidth},RESOLUTION={}x{},CODECS=\"{codecs}\"\n{}/{RENDITION_PLAYLIST}",
+            output.width,
+            output.height,
+            output.rendition.name,
+        );
+    }
+
+    fs::write(output_dir.join(MASTER_PLAYLIST), playlist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_follows_resolution_and_frame_rate() {
+        assert_eq!(h264_level(426, 240, Rational(30, 1), 600_000), 21);
+        assert_eq!(h264_level(854, 480, Rational(30, 1), 1_500_000), 31);
+        assert_eq!(h264_level(1280, 720, Rational(30, 1), 4_200_000), 31);
+        assert_eq!(h264_level(1280, 720, Rational(60, 1), 4_200_000), 32);
+        assert_eq!(h264_level(1920, 1080, Rational(30, 1), 7_500_000), 40);
+        assert_eq!(h264_level(1920, 1080, Rational(60, 1), 7_500_000), 42);
+    }
+
+    #[test]
+    fn level_follows_bitrate() {
+        assert_eq!(h264_level(640, 360, Rational(30, 1), 20_000_000), 32);
+        assert_eq!(h264_level(1920, 1080, Rational(30, 1), 30_000_000), 41);
+    }
+
+    #[test]
+    fn level_handles_fractional_frame_rates() {
+        // 29.97 fps stays within level 3.1 at 720p
+        assert_eq!(
+            h264_level(1280, 720, Rational(30_000, 1_001), 4_200_000),
+            31
+        );
+    }
+
+    #[test]
+    fn level_saturates_beyond_the_table() {
+        assert_eq!(h264_level(7680, 4320, Rational(60, 1), 500_000_000), 52);
+    }
+
+    #[test]
+    fn ladder_levels_fit_portrait_sources() {
+        // Same macroblock count as landscape, so the same level
+        assert_eq!(h264_level(720, 1280, Rational(30, 1), 4_200_000), 31);
+    }
+
+    #[test]
+    fn codec_string_is_high_profile_with_hex_level() {
+        assert_eq!(h264_codec_string(31), "avc1.64001f");
+        assert_eq!(h264_codec_string(40), "avc1.640028");
+        assert_eq!(h264_codec_string(21), "avc1.640015");
+    }
+}
@@ -0,0 +1,96 @@
+//! Deleted videos stay in the trash, restorable by their author, for
+//! `config::trash_retention`. Past that, the sweeper removes everything
+//! stored under their bucket and the rows that depend on them.
+
+use crate::{config, models, schema, AppState};
+
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Videos purged per query, the sweep goes on until none is left
+const BATCH_SIZE: i64 = 100;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Purge expired videos forever
+pub async fn run(state: AppState) {
+    tracing::info!("trash sweeper started");
+
+    loop {
+        match sweep(&state).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!(purged, "purged videos from the trash"),
+            Err(err) => tracing::error!("cannot sweep the trash: {err}"),
+        }
+
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+    }
+}
+
+/// Whether a video deleted at `deleted_at` can still be restored
+pub async fn is_restorable(deleted_at: chrono::DateTime<chrono::Utc>) -> bool {
+    deleted_at + config::config().await.trash_retention() > chrono::Utc::now()
+}
+
+/// Purge every video whose retention period is over. Returns how many were.
+async fn sweep(state: &AppState) -> Result<usize, BoxError> {
+    use schema::videos::dsl::{deleted_at, id, videos};
+
+    let expired_before = chrono::Utc::now() - config::config().await.trash_retention();
+    let mut purged = 0;
+
+    // Left for the next sweep, so that they don't come back in every batch
+    let mut failed = Vec::new();
+
+    loop {
+        let mut conn = state.db_pool.get().await?;
+
+        let expired = videos
+            .select(models::VIDEO_ALL_COLUMNS)
+            .filter(deleted_at.lt(expired_before))
+            .filter(id.ne_all(failed.clone()))
+            .order(deleted_at)
+            .limit(BATCH_SIZE)
+            .load::<models::Video>(&mut conn)
+            .await?;
+
+        drop(conn);
+
+        if expired.is_empty() {
+            return Ok(purged);
+        }
+
+        for video in expired {
+            if let Err(err) = purge(state, &video).await {
+                tracing::warn!(video_id = video.id, "cannot purge video: {err}");
+                failed.push(video.id);
+                continue;
+            }
+
+            purged += 1;
+        }
+    }
+}
+
+/// Delete the original, renditions and thumbnails, then the row itself which
+/// cascades to likes, jobs and the rest
+async fn purge(state: &AppState, video: &models::Video) -> Result<(), BoxError> {
+    use schema::videos::dsl::videos;
+
+    // The original is stored at the bucket id itself, everything else below it
+    for object in state.storage.list(&video.bucket.to_string()).await? {
+        state.storage.delete(&object.key).await?;
+    }
+
+    let mut conn = state.db_pool.get().await?;
+
+    diesel::delete(videos.find(video.id))
+        .execute(&mut conn)
+        .await?;
+
+    Ok(())
+}
@@ -1,8 +1,704 @@
+use crate::models;
+
+use axum::http::StatusCode;
 use chrono::Duration;
 use std::path::Path;
 
+use ffmpeg::{codec, format, frame, media, rescale, software, ChannelLayout, Rational, Rescale};
+
+/// Containers we accept, matched against the demuxer short names
+const ALLOWED_CONTAINERS: &[&str] = &[
+    "mov", "mp4", "matroska", "webm", "avi", "mpegts", "flv", "ogg",
+];
+
+const ALLOWED_VIDEO_CODECS: &[codec::Id] = &[
+    codec::Id::H264,
+    codec::Id::HEVC,
+    codec::Id::VP8,
+    codec::Id::VP9,
+    codec::Id::AV1,
+    codec::Id::MPEG4,
+    codec::Id::MPEG2VIDEO,
+    codec::Id::THEORA,
+];
+
+const IMAGE_CODECS: &[codec::Id] = &[
+    codec::Id::PNG,
+    codec::Id::MJPEG,
+    codec::Id::GIF,
+    codec::Id::WEBP,
+    codec::Id::BMP,
+    codec::Id::TIFF,
+];
+
+/// Demuxers of the still images accepted as thumbnails, avatars and banners
+const UPLOADED_IMAGE_DEMUXERS: &[&str] = &["image2", "png_pipe", "jpeg_pipe", "webp_pipe"];
+
+const UPLOADED_IMAGE_CODECS: &[codec::Id] = &[codec::Id::PNG, codec::Id::MJPEG, codec::Id::WEBP];
+
+/// Largest uploaded image we are willing to decode, in pixels
+const MAX_UPLOADED_IMAGE_PIXELS: u64 = 50_000_000;
+
+/// Names used by ffmpeg itself for the common layouts
+const CHANNEL_LAYOUT_NAMES: &[(ChannelLayout, &str)] = &[
+    (ChannelLayout::MONO, "mono"),
+    (ChannelLayout::STEREO, "stereo"),
+    (ChannelLayout::_2POINT1, "2.1"),
+    (ChannelLayout::SURROUND, "3.0"),
+    (ChannelLayout::QUAD, "quad"),
+    (ChannelLayout::_5POINT0, "5.0(side)"),
+    (ChannelLayout::_5POINT1, "5.1(side)"),
+    (ChannelLayout::_5POINT1_BACK, "5.1"),
+    (ChannelLayout::_7POINT1, "7.1"),
+];
+
+/// How many packets we are willing to read while looking for a decodable frame
+const MAX_PROBE_PACKETS: usize = 500;
+
+/// Where to look for a thumbnail, as fractions of the duration, in order of
+/// preference
+const THUMBNAIL_POSITIONS: &[f64] = &[0.25, 0.1, 0.5, 0.75, 0.0];
+
+/// Frames with a mean luma (0-255) below this are considered black
+const BLACK_FRAME_LUMA: f64 = 24.0;
+
+/// Frames whose luma deviates less than this are a single flat colour
+const FLAT_FRAME_DEVIATION: f64 = 8.0;
+
+/// MIME type of a container, given the demuxer names ffmpeg reports for it
+pub fn container_mime_type(container: &str) -> &'static str {
+    let names = container.split(',').collect::<Vec<_>>();
+
+    if names.contains(&"mp4") || names.contains(&"mov") {
+        "video/mp4"
+    } else if names.contains(&"webm") || names.contains(&"matroska") {
+        "video/webm"
+    } else if names.contains(&"ogg") {
+        "video/ogg"
+    } else if names.contains(&"mpegts") {
+        "video/mp2t"
+    } else if names.contains(&"avi") {
+        "video/x-msvideo"
+    } else if names.contains(&"flv") {
+        "video/x-flv"
+    } else {
+        "application/octet-stream"
+    }
+}
+
 pub fn get_video_duration<P: AsRef<Path>>(path: P) -> Result<Duration, ffmpeg::Error> {
     let context = ffmpeg::format::input(&path)?;
 
     Ok(Duration::microseconds(context.duration()))
 }
+
+/// Why an uploaded file was rejected
+#[derive(Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// ffmpeg could not recognize the file at all
+    UnrecognizedFormat,
+    UnsupportedContainer(String),
+    /// The file is a still image, possibly renamed to look like a video
+    StillImage,
+    /// The file only contains audio (or audio with cover art)
+    NoVideoStream,
+    UnsupportedCodec(String),
+    InvalidDuration,
+    /// The video stream is present but no frame could be decoded from it
+    Undecodable,
+    /// Frames near the end of the file are missing
+    Truncated,
+}
+
+impl ValidationError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnrecognizedFormat
+            | Self::UnsupportedContainer(_)
+            | Self::StillImage
+            | Self::UnsupportedCodec(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::NoVideoStream | Self::InvalidDuration | Self::Undecodable | Self::Truncated => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnrecognizedFormat => write!(f, "The file is not a recognized media format"),
+            Self::UnsupportedContainer(name) => write!(f, "Unsupported container format '{name}'"),
+            Self::StillImage => write!(f, "The file is an image, not a video"),
+            Self::NoVideoStream => write!(f, "The file does not contain a video stream"),
+            Self::UnsupportedCodec(name) => write!(f, "Unsupported video codec '{name}'"),
+            Self::InvalidDuration => write!(f, "The video has no positive duration"),
+            Self::Undecodable => write!(f, "The video stream cannot be decoded"),
+            Self::Truncated => write!(f, "The video file is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// What we learned about a file that passed validation
+#[derive(Debug)]
+pub struct ValidatedVideo {
+    pub duration: Duration,
+    pub metadata: models::MediaMetadata,
+}
+
+/// Check that the file at `path` is a video we are able to process: a known
+/// container holding a decodable video stream with a supported codec and a
+/// positive duration.
+pub fn validate_video<P: AsRef<Path>>(path: P) -> Result<ValidatedVideo, ValidationError> {
+    let mut context = format::input(&path).map_err(|_| ValidationError::UnrecognizedFormat)?;
+
+    let container = context.format().name().to_owned();
+
+    if !container
+        .split(',')
+        .any(|name| ALLOWED_CONTAINERS.contains(&name))
+    {
+        // Image demuxers (image2, png_pipe, ...) are the usual suspects here
+        return Err(
+            if container.contains("image") || container.ends_with("_pipe") || container == "gif" {
+                ValidationError::StillImage
+            } else {
+                ValidationError::UnsupportedContainer(container)
+            },
+        );
+    }
+
+    // Cover art is exposed as a video stream, but isn't one
+    let video_stream = context
+        .streams()
+        .filter(|stream| stream.parameters().medium() == media::Type::Video)
+        .find(|stream| {
+            !stream
+                .disposition()
+                .contains(format::stream::Disposition::ATTACHED_PIC)
+        })
+        .ok_or(ValidationError::NoVideoStream)?;
+
+    let codec_id = video_stream.parameters().id();
+
+    if IMAGE_CODECS.contains(&codec_id) && video_stream.frames() <= 1 {
+        return Err(ValidationError::StillImage);
+    }
+
+    if !ALLOWED_VIDEO_CODECS.contains(&codec_id) {
+        return Err(ValidationError::UnsupportedCodec(
+            codec_id.name().to_owned(),
+        ));
+    }
+
+    let duration = context.duration();
+
+    if duration <= 0 {
+        return Err(ValidationError::InvalidDuration);
+    }
+
+    let metadata = probe(&context, &video_stream)?;
+
+    let stream_index = video_stream.index();
+    let time_base = video_stream.time_base();
+    let start_time = video_stream.start_time();
+    let parameters = video_stream.parameters();
+
+    if !decodes_frame(&mut context, stream_index, parameters.clone())? {
+        return Err(ValidationError::Undecodable);
+    }
+
+    // A file cut short usually still decodes fine at the start, so also look
+    // close to where the end is supposed to be
+    let near_end = duration / 10 * 9;
+
+    let complete = if context.seek(near_end, ..near_end).is_ok() {
+        decodes_frame(&mut context, stream_index, parameters)?
+    } else {
+        // No seek index, as in some MPEG-TS or fragmented MP4 files, so read
+        // through and see whether the stream goes on that far
+        reaches(&mut context, stream_index, time_base, start_time, near_end)
+    };
+
+    if !complete {
+        return Err(ValidationError::Truncated);
+    }
+
+    Ok(ValidatedVideo {
+        duration: Duration::microseconds(duration),
+        metadata,
+    })
+}
+
+/// Collect the technical properties of a file, given its main video stream
+fn probe(
+    context: &format::context::Input,
+    video_stream: &format::stream::Stream,
+) -> Result<models::MediaMetadata, ValidationError> {
+    let video_decoder = codec::context::Context::from_parameters(video_stream.parameters())
+        .and_then(|context| context.decoder().video())
+        .map_err(|_| ValidationError::Undecodable)?;
+
+    let frame_rate = video_stream.avg_frame_rate();
+
+    let audio = context
+        .streams()
+        .best(media::Type::Audio)
+        .and_then(|stream| {
+            let decoder = codec::context::Context::from_parameters(stream.parameters())
+                .and_then(|context| context.decoder().audio())
+                .ok()?;
+
+            Some((stream.parameters().id(), decoder))
+        });
+
+    Ok(models::MediaMetadata {
+        container: context.format().name().to_owned(),
+        width: video_decoder.width() as i32,
+        height: video_decoder.height() as i32,
+        frame_rate: (frame_rate.numerator() > 0 && frame_rate.denominator() > 0)
+            .then(|| f64::from(frame_rate)),
+        video_codec: video_stream.parameters().id().name().to_owned(),
+        audio_codec: audio.as_ref().map(|(id, _)| id.name().to_owned()),
+        audio_channel_layout: audio
+            .as_ref()
+            .map(|(_, decoder)| channel_layout_name(decoder.channel_layout(), decoder.channels())),
+        bit_rate: Some(context.bit_rate()).filter(|&bit_rate| bit_rate > 0),
+        rotation: rotation(video_stream),
+        has_captions: context
+            .streams()
+            .any(|stream| stream.parameters().medium() == media::Type::Subtitle),
+    })
+}
+
+fn channel_layout_name(layout: ChannelLayout, channels: u16) -> String {
+    CHANNEL_LAYOUT_NAMES
+        .iter()
+        .find(|(known, _)| *known == layout)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("{channels} channels"))
+}
+
+/// Clockwise rotation in degrees, from the display matrix or the legacy
+/// `rotate` tag
+pub fn rotation(stream: &format::stream::Stream) -> i32 {
+    let degrees = stream
+        .side_data()
+        .find(|side_data| side_data.kind() == codec::packet::side_data::Type::DisplayMatrix)
+        .and_then(|side_data| display_matrix_rotation(side_data.data()))
+        .or_else(|| {
+            stream
+                .metadata()
+                .get("rotate")
+                .and_then(|value| value.parse().ok())
+        })
+        .unwrap_or(0.0);
+
+    (degrees.round() as i32).rem_euclid(360)
+}
+
+/// Same as ffmpeg's `-av_display_rotation_get`
+fn display_matrix_rotation(data: &[u8]) -> Option<f64> {
+    let matrix = data
+        .chunks_exact(4)
+        .map(|bytes| i32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64)
+        .collect::<Vec<_>>();
+
+    if matrix.len() < 9 {
+        return None;
+    }
+
+    let scale_x = matrix[0].hypot(matrix[3]);
+    let scale_y = matrix[1].hypot(matrix[4]);
+
+    if scale_x == 0.0 || scale_y == 0.0 {
+        return None;
+    }
+
+    Some(
+        (matrix[1] / scale_y)
+            .atan2(matrix[0] / scale_x)
+            .to_degrees(),
+    )
+}
+
+/// Whether the packets of the given stream go on, from the current position,
+/// until `timestamp`, in `AV_TIME_BASE` units since the start of the file
+fn reaches(
+    context: &mut format::context::Input,
+    stream_index: usize,
+    time_base: Rational,
+    start_time: i64,
+    timestamp: i64,
+) -> bool {
+    // `AV_NOPTS_VALUE` when unknown
+    let start_time = if start_time == i64::MIN {
+        0
+    } else {
+        start_time
+    };
+    let target = start_time + timestamp.rescale(rescale::TIME_BASE, time_base);
+
+    context
+        .packets()
+        .filter(|(stream, _)| stream.index() == stream_index)
+        .any(|(_, packet)| {
+            packet
+                .pts()
+                .or(packet.dts())
+                .is_some_and(|pts| pts >= target)
+        })
+}
+
+/// Try to decode a single frame of the given stream from the current position
+fn decodes_frame(
+    context: &mut format::context::Input,
+    stream_index: usize,
+    parameters: codec::Parameters,
+) -> Result<bool, ValidationError> {
+    decode_frame(context, stream_index, parameters)
+        .map(|frame| frame.is_some())
+        .map_err(|_| ValidationError::Undecodable)
+}
+
+/// Decode the first frame of the given stream found from the current position
+fn decode_frame(
+    context: &mut format::context::Input,
+    stream_index: usize,
+    parameters: codec::Parameters,
+) -> Result<Option<frame::Video>, ffmpeg::Error> {
+    let mut decoder = codec::context::Context::from_parameters(parameters)?
+        .decoder()
+        .video()?;
+
+    let mut frame = frame::Video::empty();
+
+    for (stream, packet) in context.packets().take(MAX_PROBE_PACKETS) {
+        if stream.index() != stream_index {
+            continue;
+        }
+
+        // Corrupt packets are skipped, we only care whether *something* decodes
+        if decoder.send_packet(&packet).is_err() {
+            continue;
+        }
+
+        if decoder.receive_frame(&mut frame).is_ok() {
+            return Ok(Some(frame));
+        }
+    }
+
+    let _ = decoder.send_eof();
+
+    Ok(decoder.receive_frame(&mut frame).ok().map(|_| frame))
+}
+
+/// Pick a representative frame of the video to use as its thumbnail.
+///
+/// A few positions are sampled and the first frame that is neither black nor
+/// a flat colour wins. If every candidate looks blank, the most detailed one
+/// is used anyway.
+pub fn extract_thumbnail_frame<P: AsRef<Path>>(path: P) -> Result<frame::Video, ffmpeg::Error> {
+    let mut context = format::input(&path)?;
+
+    let video_stream = context
+        .streams()
+        .best(media::Type::Video)
+        .ok_or(ffmpeg::Error::StreamNotFound)?;
+
+    let stream_index = video_stream.index();
+    let parameters = video_stream.parameters();
+    let duration = context.duration().max(0);
+
+    let mut fallback: Option<(f64, frame::Video)> = None;
+
+    for position in THUMBNAIL_POSITIONS {
+        let timestamp = (duration as f64 * position) as i64;
+
+        if context.seek(timestamp, ..=timestamp).is_err() {
+            continue;
+        }
+
+        let Some(frame) = decode_frame(&mut context, stream_index, parameters.clone())? else {
+            continue;
+        };
+
+        let (mean, deviation) = luma_stats(&frame)?;
+
+        if mean >= BLACK_FRAME_LUMA && deviation >= FLAT_FRAME_DEVIATION {
+            return Ok(frame);
+        }
+
+        if fallback.as_ref().is_none_or(|(best, _)| deviation > *best) {
+            fallback = Some((deviation, frame));
+        }
+    }
+
+    fallback
+        .map(|(_, frame)| frame)
+        .ok_or(ffmpeg::Error::InvalidData)
+}
+
+/// Decode an uploaded still image: a single PNG, JPEG or WebP frame of a
+/// reasonable size
+pub fn decode_image<P: AsRef<Path>>(path: P) -> Result<frame::Video, ffmpeg::Error> {
+    let mut context = format::input(&path)?;
+
+    if !context
+        .format()
+        .name()
+        .split(',')
+        .any(|name| UPLOADED_IMAGE_DEMUXERS.contains(&name))
+    {
+        return Err(ffmpeg::Error::InvalidData);
+    }
+
+    let image_stream = context
+        .streams()
+        .best(media::Type::Video)
+        .ok_or(ffmpeg::Error::StreamNotFound)?;
+
+    let stream_index = image_stream.index();
+    let parameters = image_stream.parameters();
+
+    if !UPLOADED_IMAGE_CODECS.contains(&parameters.id()) || image_stream.frames() > 1 {
+        return Err(ffmpeg::Error::InvalidData);
+    }
+
+    // Checked on the header alone, before allocating anything for the pixels
+    let header = codec::context::Context::from_parameters(parameters.clone())?
+        .decoder()
+        .video()?;
+
+    if u64::from(header.width()) * u64::from(header.height()) > MAX_UPLOADED_IMAGE_PIXELS {
+        return Err(ffmpeg::Error::InvalidData);
+    }
+
+    let frame = decode_frame(&mut context, stream_index, parameters.clone())?
+        .ok_or(ffmpeg::Error::InvalidData)?;
+
+    // Animations and concatenated images have more
+    if decode_frame(&mut context, stream_index, parameters)?.is_some() {
+        return Err(ffmpeg::Error::InvalidData);
+    }
+
+    Ok(frame)
+}
+
+/// Mean and standard deviation of the brightness of a frame, computed on a
+/// downscaled grayscale copy
+fn luma_stats(frame: &frame::Video) -> Result<(f64, f64), ffmpeg::Error> {
+    const SAMPLE_WIDTH: u32 = 64;
+    const SAMPLE_HEIGHT: u32 = 36;
+
+    let mut scaler = software::scaling::Context::get(
+        frame.format(),
+        frame.width(),
+        frame.height(),
+        format::Pixel::GRAY8,
+        SAMPLE_WIDTH,
+        SAMPLE_HEIGHT,
+        software::scaling::Flags::AREA,
+    )?;
+
+    let mut gray = frame::Video::empty();
+    scaler.run(frame, &mut gray)?;
+
+    let stride = gray.stride(0);
+    let data = gray.data(0);
+
+    let pixels = (0..SAMPLE_HEIGHT as usize)
+        .flat_map(|row| &data[row * stride..row * stride + SAMPLE_WIDTH as usize])
+        .map(|&luma| luma as f64)
+        .collect::<Vec<_>>();
+
+    let count = pixels.len() as f64;
+    let mean = pixels.iter().sum::<f64>() / count;
+    let variance = pixels.iter().map(|luma| (luma - mean).powi(2)).sum::<f64>() / count;
+
+    Ok((mean, variance.sqrt()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use base64::Engine;
+    use ffmpeg::{encoder, Dictionary, Packet};
+
+    const FRAME_RATE: i32 = 25;
+    const FRAME_SIZE: u32 = 64;
+    const SAMPLE_RATE: i32 = 48_000;
+
+    /// A 1x1 grayscale PNG
+    const PNG: &str =
+        "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNkYAAAAAYAAjCB0C8AAAAASUVORK5CYII=";
+
+    fn temp_file(suffix: &str) -> tempfile::NamedTempFile {
+        tempfile::Builder::new().suffix(suffix).tempfile().unwrap()
+    }
+
+    fn write_packets(
+        encoder: &mut encoder::Encoder,
+        octx: &mut format::context::Output,
+        encoder_time_base: Rational,
+    ) {
+        let stream_time_base = octx.stream(0).unwrap().time_base();
+        let mut packet = Packet::empty();
+
+        while encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(0);
+            packet.rescale_ts(encoder_time_base, stream_time_base);
+            packet.write_interleaved(octx).unwrap();
+        }
+    }
+
+    /// Encode `seconds` of noise as MPEG-4 video, with the index at the start
+    /// of the file so that a truncated copy still opens
+    fn write_video(path: &Path, seconds: i32) {
+        ffmpeg::init().unwrap();
+
+        let time_base = Rational(1, FRAME_RATE);
+        let mut octx = format::output(&path).unwrap();
+        let global_header = octx.format().flags().contains(format::Flags::GLOBAL_HEADER);
+
+        let codec = encoder::find(codec::Id::MPEG4).unwrap();
+        let mut stream = octx.add_stream(codec).unwrap();
+
+        let mut encoder = codec::context::Context::new().encoder().video().unwrap();
+        encoder.set_width(FRAME_SIZE);
+        encoder.set_height(FRAME_SIZE);
+        encoder.set_format(format::Pixel::YUV420P);
+        encoder.set_time_base(time_base);
+        encoder.set_frame_rate(Some((FRAME_RATE, 1)));
+        if global_header {
+            encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let mut encoder = encoder.open_as(codec).unwrap();
+        stream.set_parameters(&encoder);
+        stream.set_time_base(time_base);
+
+        let mut options = Dictionary::new();
+        options.set("movflags", "faststart");
+        octx.write_header_with(options).unwrap();
+
+        let mut frame = frame::Video::new(format::Pixel::YUV420P, FRAME_SIZE, FRAME_SIZE);
+        let mut noise = 1_u32;
+
+        for index in 0..i64::from(seconds * FRAME_RATE) {
+            for plane in 0..3 {
+                for byte in frame.data_mut(plane) {
+                    noise = noise.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    *byte = (noise >> 16) as u8;
+                }
+            }
+
+            frame.set_pts(Some(index));
+            encoder.send_frame(&frame).unwrap();
+            write_packets(&mut encoder, &mut octx, time_base);
+        }
+
+        encoder.send_eof().unwrap();
+        write_packets(&mut encoder, &mut octx, time_base);
+        octx.write_trailer().unwrap();
+    }
+
+    /// Encode `seconds` of silence as PCM in a Matroska file
+    fn write_audio(path: &Path, seconds: i32) {
+        ffmpeg::init().unwrap();
+
+        const FRAME_SAMPLES: usize = 1024;
+
+        let time_base = Rational(1, SAMPLE_RATE);
+        let sample_format = format::Sample::I16(format::sample::Type::Packed);
+        let mut octx = format::output(&path).unwrap();
+
+        let codec = encoder::find(codec::Id::PCM_S16LE).unwrap();
+        let mut stream = octx.add_stream(codec).unwrap();
+
+        let mut encoder = codec::context::Context::new().encoder().audio().unwrap();
+        encoder.set_rate(SAMPLE_RATE);
+        encoder.set_channel_layout(ChannelLayout::MONO);
+        encoder.set_channels(ChannelLayout::MONO.channels());
+        encoder.set_format(sample_format);
+        encoder.set_time_base(time_base);
+
+        let mut encoder = encoder.open_as(codec).unwrap();
+        stream.set_parameters(&encoder);
+        stream.set_time_base(time_base);
+
+        octx.write_header().unwrap();
+
+        let mut frame = frame::Audio::new(sample_format, FRAME_SAMPLES, ChannelLayout::MONO);
+        frame.set_rate(SAMPLE_RATE as u32);
+
+        let frames = SAMPLE_RATE as usize * seconds as usize / FRAME_SAMPLES;
+
+        for index in 0..frames {
+            frame.set_pts(Some((index * FRAME_SAMPLES) as i64));
+            encoder.send_frame(&frame).unwrap();
+            write_packets(&mut encoder, &mut octx, time_base);
+        }
+
+        encoder.send_eof().unwrap();
+        write_packets(&mut encoder, &mut octx, time_base);
+        octx.write_trailer().unwrap();
+    }
+
+    #[test]
+    fn accepts_a_complete_video() {
+        let file = temp_file(".mp4");
+        write_video(file.path(), 4);
+
+        let validated = validate_video(file.path()).unwrap();
+
+        assert_eq!(validated.duration.num_seconds(), 4);
+        assert_eq!(validated.metadata.width, FRAME_SIZE as i32);
+    }
+
+    #[test]
+    fn rejects_a_truncated_video() {
+        let file = temp_file(".mp4");
+        write_video(file.path(), 4);
+
+        let data = std::fs::read(file.path()).unwrap();
+        let truncated = temp_file(".mp4");
+        std::fs::write(truncated.path(), &data[..data.len() / 2]).unwrap();
+
+        assert!(matches!(
+            validate_video(truncated.path()),
+            Err(ValidationError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_audio_only_files() {
+        let file = temp_file(".mkv");
+        write_audio(file.path(), 2);
+
+        assert!(matches!(
+            validate_video(file.path()),
+            Err(ValidationError::NoVideoStream)
+        ));
+    }
+
+    #[test]
+    fn rejects_images_posing_as_video() {
+        ffmpeg::init().unwrap();
+
+        // The extension is a lie, the content is probed
+        let file = temp_file(".mp4");
+        let png = base64::engine::general_purpose::STANDARD
+            .decode(PNG)
+            .unwrap();
+        std::fs::write(file.path(), png).unwrap();
+
+        assert!(matches!(
+            validate_video(file.path()),
+            Err(ValidationError::StillImage)
+        ));
+    }
+}
@@ -0,0 +1,188 @@
+//! View counting. The player reports regularly that a viewer is watching a
+//! video, which counts as a view once they have watched for a while, and not
+//! again for the same viewer until the dedup window has passed. Views are
+//! buffered in memory and added to `video_stats` in batches.
+//!
+//! Everything here is per process: a restart forgets who was counted and
+//! loses the views of at most one flush interval.
+
+use crate::{config, AppState};
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use diesel::sql_types::{Array, BigInt, Integer};
+use diesel_async::RunQueryDsl;
+use sha2::{Digest, Sha256};
+
+/// Who is watching, anonymous viewers are only known by a salted hash of
+/// their address and user agent
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Viewer {
+    User(i32),
+    Anonymous([u8; 16]),
+}
+
+#[derive(Debug)]
+pub struct ViewCounter {
+    /// Random for each process, so that fingerprints can't be reversed by
+    /// hashing every address
+    salt: [u8; 16],
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// When each viewer first reported watching each video
+    watching: HashMap<(Viewer, i32), Instant>,
+    /// When each viewer was last counted for each video
+    counted: HashMap<(Viewer, i32), Instant>,
+    /// Views not written to the database yet, by video id
+    pending: HashMap<i32, i64>,
+}
+
+impl Default for ViewCounter {
+    fn default() -> Self {
+        Self {
+            salt: uuid::Uuid::new_v4().into_bytes(),
+            inner: Mutex::default(),
+        }
+    }
+}
+
+impl ViewCounter {
+    pub fn anonymous_viewer(&self, address: IpAddr, user_agent: &str) -> Viewer {
+        let digest = Sha256::new()
+            .chain_update(self.salt)
+            .chain_update(address.to_string())
+            .chain_update([0])
+            .chain_update(user_agent)
+            .finalize();
+
+        let mut fingerprint = [0; 16];
+        fingerprint.copy_from_slice(&digest[..16]);
+
+        Viewer::Anonymous(fingerprint)
+    }
+
+    /// Record that `viewer` is watching `video_id`, which lasts `duration`.
+    /// Returns whether this counted as a view.
+    ///
+    /// Only the time since the first report is trusted, whatever the client
+    /// says about how much it played.
+    pub fn heartbeat(
+        &self,
+        viewer: Viewer,
+        video_id: i32,
+        duration: Duration,
+        min_watch: Duration,
+        dedup_window: Duration,
+    ) -> bool {
+        let threshold = min_watch.min(duration);
+        let key = (viewer, video_id);
+        let now = Instant::now();
+
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(counted_at) = inner.counted.get(&key) {
+            if now.duration_since(*counted_at) < dedup_window {
+                return false;
+            }
+        }
+
+        let started_at = *inner.watching.entry(key.clone()).or_insert(now);
+
+        if now.duration_since(started_at) < threshold {
+            return false;
+        }
+
+        inner.watching.remove(&key);
+        inner.counted.insert(key, now);
+        *inner.pending.entry(video_id).or_default() += 1;
+
+        true
+    }
+
+    /// Forget viewers who were counted or started watching before the window
+    fn prune(&self, dedup_window: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner
+            .counted
+            .retain(|_, counted_at| counted_at.elapsed() < dedup_window);
+        inner
+            .watching
+            .retain(|_, started_at| started_at.elapsed() < dedup_window);
+    }
+
+    fn take_pending(&self) -> HashMap<i32, i64> {
+        std::mem::take(&mut self.inner.lock().unwrap().pending)
+    }
+
+    /// Put back views which couldn't be written, to retry with the next batch
+    fn restore_pending(&self, views: HashMap<i32, i64>) {
+        let mut inner = self.inner.lock().unwrap();
+
+        for (video_id, count) in views {
+            *inner.pending.entry(video_id).or_default() += count;
+        }
+    }
+}
+
+/// Flush the buffered views forever, as configured
+pub async fn run(state: AppState) {
+    let config = config::config().await;
+
+    loop {
+        tokio::time::sleep(config.view_flush_interval()).await;
+
+        state.views.prune(config.view_dedup_window());
+
+        if let Err(err) = flush(&state).await {
+            tracing::error!("cannot flush views: {err}");
+        }
+    }
+}
+
+/// Add the buffered views to the counts of their videos in a single query
+pub async fn flush(state: &AppState) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let views = state.views.take_pending();
+
+    if views.is_empty() {
+        return Ok(());
+    }
+
+    let (video_ids, counts): (Vec<i32>, Vec<i64>) = views
+        .iter()
+        .map(|(video_id, count)| (*video_id, *count))
+        .unzip();
+
+    let written = async {
+        let mut conn = state.db_pool.get().await?;
+
+        // Videos purged since they were viewed are skipped
+        diesel::sql_query(
+            "insert into video_stats (video_id, view_count) \
+             select views.video_id, views.count \
+             from unnest($1, $2) as views (video_id, count) \
+             where exists (select 1 from videos where videos.id = views.video_id) \
+             on conflict (video_id) do update \
+             set view_count = video_stats.view_count + excluded.view_count",
+        )
+        .bind::<Array<Integer>, _>(&video_ids)
+        .bind::<Array<BigInt>, _>(&counts)
+        .execute(&mut conn)
+        .await?;
+
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+    }
+    .await;
+
+    if written.is_err() {
+        state.views.restore_pending(views);
+    }
+
+    written
+}
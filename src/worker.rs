@@ -0,0 +1,245 @@
+use crate::jobs::Heartbeat;
+use crate::storage::{Storage, StorageError};
+use crate::{jobs, models, schema, thumbnail, transcode, video_util, AppState};
+
+use std::path::Path;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use tokio::io::AsyncWriteExt;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Process queued videos forever
+pub async fn run(state: AppState) {
+    tracing::info!("video processing worker started");
+
+    loop {
+        match process_next(&state).await {
+            // There may be more work waiting, don't sleep
+            Ok(true) => {}
+            Ok(false) => tokio::time::sleep(POLL_INTERVAL).await,
+            Err(err) => {
+                tracing::error!("cannot process job: {err}");
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Claim and run a single job. Returns `false` when the queue is empty.
+async fn process_next(state: &AppState) -> Result<bool, BoxError> {
+    let mut conn = state.db_pool.get().await?;
+
+    let Some(job) = jobs::claim_next(&mut conn).await? else {
+        return Ok(false);
+    };
+
+    // Don't keep a pooled connection busy while transcoding
+    drop(conn);
+
+    tracing::info!(
+        job_id = job.id,
+        video_id = job.video_id,
+        attempt = job.attempts,
+        "processing video"
+    );
+
+    // Processing stops as soon as the job isn't ours to finish anymore
+    let result = tokio::select! {
+        result = process_video(state, job.video_id) => result,
+        heartbeat = keep_locked(state, &job) => {
+            let mut conn = state.db_pool.get().await?;
+
+            if heartbeat == Heartbeat::VideoDeleted {
+                jobs::release(&mut conn, &job).await?;
+            }
+
+            tracing::warn!(
+                job_id = job.id,
+                video_id = job.video_id,
+                ?heartbeat,
+                "video processing interrupted"
+            );
+
+            return Ok(true);
+        }
+    };
+
+    let mut conn = state.db_pool.get().await?;
+
+    match result {
+        Ok(()) => {
+            if jobs::complete(&mut conn, &job).await? {
+                tracing::info!(job_id = job.id, video_id = job.video_id, "video ready");
+            } else {
+                tracing::warn!(
+                    job_id = job.id,
+                    video_id = job.video_id,
+                    "job reclaimed before it completed, result dropped"
+                );
+            }
+        }
+        Err(err) => match jobs::fail(&mut conn, &job, &err.to_string()).await? {
+            Some(new_state) => tracing::warn!(
+                job_id = job.id,
+                video_id = job.video_id,
+                ?new_state,
+                "cannot process video: {err}"
+            ),
+            None => tracing::warn!(
+                job_id = job.id,
+                video_id = job.video_id,
+                "job reclaimed before it failed: {err}"
+            ),
+        },
+    }
+
+    Ok(true)
+}
+
+/// Refresh the lock of `job` until it is lost or its video is deleted
+async fn keep_locked(state: &AppState, job: &models::Job) -> Heartbeat {
+    let mut interval = tokio::time::interval(jobs::LOCK_REFRESH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let heartbeat = match state.db_pool.get().await {
+            Ok(mut conn) => jobs::heartbeat(&mut conn, job)
+                .await
+                .map_err(BoxError::from),
+            Err(err) => Err(err.into()),
+        };
+
+        match heartbeat {
+            Ok(Heartbeat::Held) => {}
+            Ok(interruption) => return interruption,
+            // The lock outlives a few missed refreshes
+            Err(err) => tracing::warn!(job_id = job.id, "cannot refresh job lock: {err}"),
+        }
+    }
+}
+
+async fn process_video(state: &AppState, target_video_id: i32) -> Result<(), BoxError> {
+    use schema::videos::dsl::{duration_seconds, master_playlist, videos};
+
+    let video = {
+        let mut conn = state.db_pool.get().await?;
+
+        videos
+            .find(target_video_id)
+            .select(models::Video::as_select())
+            .first(&mut conn)
+            .await?
+    };
+
+    let source = tempfile::NamedTempFile::new()?;
+    let mut source_file = tokio::fs::File::from_std(source.reopen()?);
+
+    state
+        .storage
+        .get_range(&video.bucket.to_string(), 0, None, &mut source_file)
+        .await?;
+    source_file.flush().await?;
+
+    let video_duration = video_util::get_video_duration(source.path())?;
+
+    let hls_dir = tempfile::tempdir()?;
+
+    let input_path = source.path().to_owned();
+    let output_path = hls_dir.path().to_owned();
+
+    // The author may already have picked their own thumbnail
+    let wants_thumbnail = video.thumbnail.is_none();
+
+    // Transcoding is CPU bound, keep it off the async workers
+    let thumbnails = tokio::task::spawn_blocking(move || -> Result<_, BoxError> {
+        transcode::transcode_hls(&input_path, output_path)?;
+
+        if !wants_thumbnail {
+            return Ok(None);
+        }
+
+        let frame = video_util::extract_thumbnail_frame(&input_path)?;
+
+        Ok(Some(thumbnail::encode_all(&frame)?))
+    })
+    .await??;
+
+    let hls_prefix = format!("{}/hls", video.bucket);
+
+    upload_directory(state.storage.as_ref(), hls_dir.path(), &hls_prefix).await?;
+
+    let mut conn = state.db_pool.get().await?;
+
+    diesel::update(videos.find(target_video_id))
+        .set((
+            duration_seconds.eq(video_duration.num_seconds()),
+            master_playlist.eq(format!("{hls_prefix}/{}", transcode::MASTER_PLAYLIST)),
+        ))
+        .execute(&mut conn)
+        .await?;
+
+    if let Some(thumbnails) = thumbnails {
+        let generated = thumbnail::upload(state.storage.as_ref(), video.bucket, thumbnails).await?;
+
+        // Unless a custom one was uploaded in the meantime
+        let updated = diesel::update(videos.find(target_video_id))
+            .filter(schema::videos::thumbnail.is_null())
+            .set(schema::videos::thumbnail.eq(generated))
+            .execute(&mut conn)
+            .await?;
+
+        if updated == 0 {
+            thumbnail::delete(state.storage.as_ref(), video.bucket, generated).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Upload every file under `dir` to the storage, keeping the directory layout
+/// below `prefix`
+async fn upload_directory(
+    storage: &dyn Storage,
+    dir: &Path,
+    prefix: &str,
+) -> Result<(), StorageError> {
+    let mut pending = vec![dir.to_owned()];
+
+    while let Some(current) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(&current).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+
+            if entry.file_type().await?.is_dir() {
+                pending.push(path);
+                continue;
+            }
+
+            let relative = path.strip_prefix(dir).unwrap_or(&path);
+            let key = format!("{prefix}/{}", relative.to_string_lossy());
+
+            let mut file = tokio::fs::File::open(&path).await?;
+
+            storage
+                .put_stream(&key, &mut file, content_type(&path))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("ts") => "video/mp2t",
+        _ => "application/octet-stream",
+    }
+}